@@ -0,0 +1,422 @@
+use std::collections::BTreeMap;
+use std::io::{Cursor, Read};
+use std::path::Path;
+
+use crate::manifest::{PackManifest, StickerManifest};
+use crate::metadata::{ProductInfo, META_FILE_NAME};
+use crate::template::PathValues;
+use crate::{
+    ensure_success, ensure_unique_paths, fetch, postprocess_pack, write_file_atomically, Asset,
+    Config,
+};
+
+/// Archives served by the CDN for every pack. Animated, sound and popup packs are only
+/// complete in `stickerpack@2x.zip`, static packs only have `stickers@2x.zip`.
+const ARCHIVE_NAMES: [&str; 2] = ["stickerpack@2x.zip", "stickers@2x.zip"];
+
+pub fn archive_urls(cdn_url: &str, pack_id: &str) -> Vec<String> {
+    ARCHIVE_NAMES
+        .iter()
+        .map(|name| {
+            format!(
+                "{}/stickershop/v1/product/{}/iphone/{}",
+                cdn_url, pack_id, name
+            )
+        })
+        .collect()
+}
+
+/// Downloads a pack from its CDN archive and extracts the selected assets.
+///
+/// Returns `Ok(false)` without touching the output directory when the CDN has no
+/// archive for the pack, so the caller can fall back to the store page.
+pub async fn download_pack(
+    config: &Config,
+    source_url: &str,
+    pack_id: &str,
+    lang: &str,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    let mut archive = None;
+    for url in archive_urls(&config.cdn_url, pack_id) {
+        let response = fetch(config, &url).await?;
+        if matches!(response.status.as_u16(), 403 | 404) {
+            continue;
+        }
+        ensure_success(&url, &response)?;
+        archive = Some((url, response.body));
+        break;
+    }
+    let Some((archive_url, body)) = archive else {
+        return Ok(false);
+    };
+
+    let mut archive = zip::ZipArchive::new(Cursor::new(body))
+        .map_err(|err| format!("Invalid pack archive {}: {}", archive_url, err))?;
+    let product_info = ProductInfo::parse(&read_entry(&mut archive, META_FILE_NAME)?)
+        .map_err(|err| format!("{} in {}", err, archive_url))?;
+
+    let title = product_info
+        .title(lang)
+        .ok_or_else(|| format!("{} in {} has no title", META_FILE_NAME, archive_url))?;
+    println!("Downloading {}", title);
+
+    let author = product_info.author(lang);
+    let sticker_type = product_info.sticker_type();
+
+    let mut sticker_files = Vec::new();
+    for (position, sticker) in product_info.stickers.iter().enumerate() {
+        let sticker_id = sticker.id.to_string();
+        let mut files = Vec::new();
+        for (asset, entry) in find_sticker_entries(&archive, &sticker_id, &config.assets) {
+            let ext = Path::new(&entry)
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .ok_or("Could not extract file extension from archive entry")?;
+            let path = config.template.render(&PathValues {
+                pack_id,
+                title: &title,
+                author: author.as_deref().unwrap_or_default(),
+                lang,
+                sticker_id: &sticker_id,
+                index: position + 1,
+                sticker_type: sticker_type.as_deref().unwrap_or_default(),
+                suffix: asset.suffix(),
+                ext,
+            });
+            files.push((asset, entry, config.output.join(path)));
+        }
+        sticker_files.push((sticker_id, files));
+    }
+    ensure_unique_paths(
+        sticker_files
+            .iter()
+            .flat_map(|(_, files)| files)
+            .map(|(_, _, path)| path),
+    )?;
+
+    for (_, entry, path) in sticker_files.iter().flat_map(|(_, files)| files) {
+        if config.dry_run {
+            println!("Would extract {} to {}", entry, path.display());
+            continue;
+        }
+        extract_entry(config, &mut archive, entry, path)?;
+    }
+
+    if config.dry_run {
+        return Ok(true);
+    }
+
+    let stickers = sticker_files
+        .iter()
+        .enumerate()
+        .zip(&product_info.stickers)
+        .map(|((position, (sticker_id, _)), info)| StickerManifest {
+            id: sticker_id.clone(),
+            order: position + 1,
+            sticker_type: sticker_type.clone(),
+            width: info.width,
+            height: info.height,
+            text_area: None,
+            urls: BTreeMap::new(),
+            files: Vec::new(),
+        })
+        .collect();
+    let sticker_files: Vec<Vec<_>> = sticker_files
+        .into_iter()
+        .map(|(_, files)| {
+            files
+                .into_iter()
+                .map(|(asset, entry, path)| (asset, format!("{}#{}", archive_url, entry), path))
+                .collect()
+        })
+        .collect();
+    postprocess_pack(
+        config,
+        PackManifest {
+            pack_id: Some(pack_id.to_string()),
+            title,
+            author,
+            description: None,
+            lang: lang.to_string(),
+            source_url: source_url.to_string(),
+            downloaded_at: humantime::format_rfc3339_seconds(std::time::SystemTime::now())
+                .to_string(),
+            stickers,
+        },
+        &sticker_files,
+    )
+    .await?;
+
+    Ok(true)
+}
+
+/// Returns the archive entries of a sticker, limited to the given assets and in the same
+/// order as the files downloaded from the store page.
+fn find_sticker_entries<R: Read + std::io::Seek>(
+    archive: &zip::ZipArchive<R>,
+    sticker_id: &str,
+    assets: &[Asset],
+) -> Vec<(Asset, String)> {
+    let find = |name: String| archive.index_for_name(&name).map(|_| name);
+    let static_entry = || find(format!("{}@2x.png", sticker_id));
+    let animation_entry = || find(format!("animation@2x/{}@2x.png", sticker_id));
+
+    let entries = [
+        (Asset::Sound, find(format!("sound/{}.m4a", sticker_id))),
+        (Asset::Image, animation_entry().or_else(static_entry)),
+        (Asset::Static, static_entry()),
+        (Asset::Animation, animation_entry()),
+        (Asset::FallbackStatic, static_entry()),
+        (Asset::Popup, find(format!("popup/{}.png", sticker_id))),
+    ];
+
+    entries
+        .into_iter()
+        .filter(|(asset, _)| assets.contains(asset))
+        .filter_map(|(asset, entry)| Some((asset, entry?)))
+        .collect()
+}
+
+fn read_entry<R: Read + std::io::Seek>(
+    archive: &mut zip::ZipArchive<R>,
+    name: &str,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let mut file = archive
+        .by_name(name)
+        .map_err(|err| format!("Could not read {} from the pack archive: {}", name, err))?;
+    let mut contents = Vec::new();
+    file.read_to_end(&mut contents)?;
+
+    Ok(contents)
+}
+
+fn extract_entry<R: Read + std::io::Seek>(
+    config: &Config,
+    archive: &mut zip::ZipArchive<R>,
+    entry: &str,
+    file_path: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let contents = read_entry(archive, entry)?;
+
+    if !config.force && std::fs::read(file_path).is_ok_and(|existing| existing == contents) {
+        if config.verbose {
+            println!("Skipping {} (up to date)", file_path.display());
+        }
+        return Ok(());
+    }
+
+    if let Some(parent) = file_path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    write_file_atomically(file_path, &contents)?;
+    if config.verbose {
+        println!("Extracted {}", file_path.display());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::path::PathBuf;
+
+    const PNG_BYTES: &[u8] = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+
+    fn zip_archive(entries: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        let options = zip::write::SimpleFileOptions::default()
+            .compression_method(zip::CompressionMethod::Stored);
+        for (name, contents) in entries {
+            writer.start_file(*name, options).unwrap();
+            writer.write_all(contents).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    fn delete_directory_if_exists(directory: &Path) {
+        if directory.exists() {
+            std::fs::remove_dir_all(directory).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_archive_urls() {
+        assert_eq!(
+            archive_urls(crate::CDN_URL, "6920150"),
+            vec![
+                "https://stickershop.line-scdn.net/stickershop/v1/product/6920150/iphone/stickerpack@2x.zip",
+                "https://stickershop.line-scdn.net/stickershop/v1/product/6920150/iphone/stickers@2x.zip",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_find_sticker_entries() {
+        let archive = zip_archive(&[
+            ("1@2x.png", PNG_BYTES),
+            ("animation@2x/1@2x.png", PNG_BYTES),
+            ("sound/1.m4a", b""),
+            ("2@2x.png", PNG_BYTES),
+        ]);
+        let archive = zip::ZipArchive::new(Cursor::new(archive)).unwrap();
+
+        let actual = find_sticker_entries(&archive, "1", &[Asset::Image, Asset::Sound]);
+        assert_eq!(
+            actual,
+            vec![
+                (Asset::Sound, "sound/1.m4a".to_string()),
+                (Asset::Image, "animation@2x/1@2x.png".to_string()),
+            ]
+        );
+
+        let actual = find_sticker_entries(&archive, "2", &Asset::ALL_VARIANTS);
+        assert_eq!(
+            actual,
+            vec![
+                (Asset::Static, "2@2x.png".to_string()),
+                (Asset::FallbackStatic, "2@2x.png".to_string()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_download_pack() {
+        let mut server = mockito::Server::new_async().await;
+        let url = server.url();
+
+        let archive = zip_archive(&[
+            (
+                META_FILE_NAME,
+                br#"{"packageId":1,"title":{"en":"Test Archive","ja":"Test Archive JA"},"author":{"en":"Author"},"stickers":[{"id":20},{"id":10,"width":320,"height":296}],"stickerResourceType":"ANIMATION"}"#,
+            ),
+            ("10@2x.png", PNG_BYTES),
+            ("animation@2x/10@2x.png", PNG_BYTES),
+            ("20@2x.png", PNG_BYTES),
+            ("animation@2x/20@2x.png", PNG_BYTES),
+            ("tab_on@2x.png", PNG_BYTES),
+        ]);
+        let _m = server
+            .mock("GET", "/stickershop/v1/product/1/iphone/stickerpack@2x.zip")
+            .with_status(200)
+            .with_body(archive)
+            .create_async()
+            .await;
+
+        let output = Path::new("Test Archive Output");
+        let config = Config {
+            output: output.to_path_buf(),
+            cdn_url: url.clone(),
+            ..Config::default()
+        };
+        let actual = download_pack(&config, "source", "1", "en").await;
+        assert!(actual.as_ref().is_ok_and(|found| *found), "{:?}", actual);
+
+        let directory = output.join("Test Archive");
+        assert!(directory.join("01_20.png").is_file());
+        assert!(directory.join("02_10.png").is_file());
+
+        let manifest: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(directory.join("pack.json")).unwrap()).unwrap();
+        delete_directory_if_exists(output);
+
+        assert_eq!(manifest["pack_id"], "1");
+        assert_eq!(manifest["author"], "Author");
+        let sticker = &manifest["stickers"][1];
+        assert_eq!(sticker["id"], "10");
+        assert_eq!(sticker["order"], 2);
+        assert_eq!(sticker["type"], "animation");
+        assert_eq!(sticker["width"], 320);
+        assert_eq!(
+            sticker["files"][0]["url"],
+            format!(
+                "{}/stickershop/v1/product/1/iphone/stickerpack@2x.zip#animation@2x/10@2x.png",
+                url
+            )
+            .as_str()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_download_pack_static_archive() {
+        let mut server = mockito::Server::new_async().await;
+
+        let archive = zip_archive(&[
+            (
+                META_FILE_NAME,
+                br#"{"packageId":2,"title":{"en":"Test Static Archive"},"stickers":[{"id":10}],"stickerResourceType":"STATIC"}"#,
+            ),
+            ("10@2x.png", PNG_BYTES),
+        ]);
+        let _m = server
+            .mock("GET", "/stickershop/v1/product/2/iphone/stickerpack@2x.zip")
+            .with_status(404)
+            .create_async()
+            .await;
+        let _m2 = server
+            .mock("GET", "/stickershop/v1/product/2/iphone/stickers@2x.zip")
+            .with_status(200)
+            .with_body(archive)
+            .create_async()
+            .await;
+
+        let output = Path::new("Test Static Archive Output");
+        let config = Config {
+            output: output.to_path_buf(),
+            cdn_url: server.url(),
+            ..Config::default()
+        };
+        let actual = download_pack(&config, "source", "2", "en").await;
+        assert!(actual.as_ref().is_ok_and(|found| *found), "{:?}", actual);
+
+        let file_path = output.join("Test Static Archive").join("01_10.png");
+        let contents = std::fs::read(&file_path).unwrap();
+        delete_directory_if_exists(output);
+        assert_eq!(contents, PNG_BYTES);
+    }
+
+    #[tokio::test]
+    async fn test_download_pack_unavailable() {
+        let mut server = mockito::Server::new_async().await;
+
+        let _m = server
+            .mock("GET", mockito::Matcher::Any)
+            .with_status(404)
+            .expect(2)
+            .create_async()
+            .await;
+
+        let config = Config {
+            output: PathBuf::from("Test Unavailable Archive Output"),
+            cdn_url: server.url(),
+            ..Config::default()
+        };
+        let actual = download_pack(&config, "source", "3", "en").await;
+        assert!(actual.as_ref().is_ok_and(|found| !*found), "{:?}", actual);
+        assert!(!config.output.exists());
+        _m.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_download_pack_invalid_archive() {
+        let mut server = mockito::Server::new_async().await;
+
+        let _m = server
+            .mock("GET", "/stickershop/v1/product/4/iphone/stickerpack@2x.zip")
+            .with_status(200)
+            .with_body("<html></html>")
+            .create_async()
+            .await;
+
+        let config = Config {
+            cdn_url: server.url(),
+            ..Config::default()
+        };
+        let actual = download_pack(&config, "source", "4", "en").await;
+        assert!(actual
+            .unwrap_err()
+            .to_string()
+            .starts_with("Invalid pack archive"));
+    }
+}
@@ -0,0 +1,526 @@
+use clap::{Args, Parser, Subcommand};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::convert::{GifOptions, DEFAULT_ALPHA_THRESHOLD, DEFAULT_WEBP_QUALITY};
+use crate::template::{PathTemplate, DEFAULT_TEMPLATE};
+use crate::{
+    build_client, Asset, Category, Config, Convert, Export, HttpOptions, Mux, Source, CDN_URL,
+    DEFAULT_JOBS, DEFAULT_RETRIES,
+};
+
+/// Simple command line tool to download stickers of LINE STORE sticker shop.
+#[derive(Debug, Parser)]
+#[command(
+    name = "line-sticker-downloader",
+    version,
+    arg_required_else_help = true
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Command,
+
+    #[command(flatten)]
+    pub options: GlobalOptions,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Download sticker packs, emoji packs or themes by product ID, store URL, share link or line:// deep link
+    Download {
+        #[arg(required = true, value_name = "URL|ID")]
+        inputs: Vec<String>,
+    },
+    /// Download every sticker pack matching a search query
+    Search {
+        #[arg(required = true, value_name = "QUERY")]
+        queries: Vec<String>,
+
+        /// Kind of products to search for
+        #[arg(long, value_enum, default_value = "sticker")]
+        category: Category,
+    },
+    /// Download every sticker pack of an author by author ID or URL
+    Author {
+        #[arg(required = true, value_name = "ID|URL")]
+        authors: Vec<String>,
+    },
+    /// Print the title and stickers of a sticker pack without downloading it
+    Info {
+        #[arg(required = true, value_name = "URL|ID")]
+        inputs: Vec<String>,
+    },
+    /// Print a shell completion script
+    Completions { shell: clap_complete::Shell },
+}
+
+#[derive(Debug, Args)]
+pub struct GlobalOptions {
+    /// Directory the sticker packs are downloaded into
+    #[arg(short, long, global = true, value_name = "DIR", default_value = ".")]
+    pub output: PathBuf,
+
+    /// Layout of the downloaded files relative to the output directory. Placeholders:
+    /// {pack_id}, {title}, {author}, {lang}, {sticker_id}, {index}, {type}, {suffix} and {ext}.
+    /// Use {index:02} to zero pad
+    #[arg(long, global = true, value_name = "TEMPLATE", default_value = DEFAULT_TEMPLATE, value_parser = PathTemplate::parse)]
+    pub template: PathTemplate,
+
+    /// Kinds of sticker and theme files to download, separated by commas
+    #[arg(
+        long,
+        global = true,
+        value_name = "ASSETS",
+        value_delimiter = ',',
+        default_value = "image,sound,popup,overlay,package"
+    )]
+    pub assets: Vec<Asset>,
+
+    /// Download every variant of a sticker: static, animation, fallback-static, popup and sound,
+    /// each with its own suffix, plus the name frame of custom stickers and the resource package of themes
+    #[arg(long, global = true, conflicts_with = "assets")]
+    pub all_variants: bool,
+
+    /// Where the stickers of a pack are downloaded from. zip downloads the whole pack archive
+    /// from the CDN in one request and falls back to the store page if there is none
+    #[arg(long, global = true, value_enum, default_value = "html")]
+    pub source: Source,
+
+    /// Text rendered onto the base image of message stickers, written next to it as *_sample.png
+    #[arg(long, global = true, value_name = "TEXT")]
+    pub sample_text: Option<String>,
+
+    /// TrueType or OpenType font the sample text is rendered with [default: a system font]
+    #[arg(long, global = true, value_name = "FILE", requires = "sample_text")]
+    pub font: Option<PathBuf>,
+
+    /// Convert stickers after downloading them, written next to the PNG. gif only converts animated stickers
+    #[arg(long, global = true, value_enum, value_name = "FORMAT")]
+    pub convert: Option<Convert>,
+
+    /// Delete the downloaded PNG of every converted sticker
+    #[arg(long, global = true, requires = "convert")]
+    pub delete_original: bool,
+
+    /// Dither converted GIFs whose frames have more colors than a GIF can hold
+    #[arg(long, global = true, requires = "convert")]
+    pub dither: bool,
+
+    /// Alpha value from which pixels of converted GIFs are opaque, pixels below it become transparent
+    #[arg(long, global = true, value_name = "0-255", default_value_t = DEFAULT_ALPHA_THRESHOLD, requires = "convert")]
+    pub alpha_threshold: u8,
+
+    /// Quality of converted WebPs. 100 is lossless, lower values drop color precision for smaller files
+    #[arg(long, global = true, value_name = "0-100", default_value_t = DEFAULT_WEBP_QUALITY, value_parser = clap::value_parser!(u8).range(0..=100))]
+    pub webp_quality: u8,
+
+    /// Chat apps every downloaded pack is exported for, separated by commas. Each export is
+    /// written into a directory of the same name inside the pack directory
+    #[arg(long, global = true, value_name = "FORMATS", value_delimiter = ',')]
+    pub export: Vec<Export>,
+
+    /// Combine the animation and sound of every sound sticker into a video looping the
+    /// animation for as long as the sound plays, written next to the sound. Needs ffmpeg
+    #[arg(long, global = true, value_enum, value_name = "FORMAT")]
+    pub mux_sound: Option<Mux>,
+
+    /// Write every frame of each animated sticker as a PNG into a directory next to it, with
+    /// a frames.json listing delays, dispose and blend ops, loop count and canvas size
+    #[arg(long, global = true)]
+    pub explode_frames: bool,
+
+    /// ffmpeg binary used to encode the videos of exports and sound stickers
+    #[arg(long, global = true, value_name = "FILE", default_value = "ffmpeg")]
+    pub ffmpeg: PathBuf,
+
+    /// Number of sticker files downloaded at the same time within a pack
+    #[arg(short, long, global = true, value_name = "N", default_value_t = DEFAULT_JOBS, value_parser = parse_jobs)]
+    pub jobs: usize,
+
+    /// Store language used for product and author IDs, e.g. en, ja or zh-Hant
+    #[arg(long, global = true, value_name = "LANG", default_value = "en")]
+    pub lang: String,
+
+    /// Print the files that would be downloaded without downloading them
+    #[arg(long, global = true)]
+    pub dry_run: bool,
+
+    /// Print every downloaded and skipped file
+    #[arg(short, long, global = true)]
+    pub verbose: bool,
+
+    /// Download every file again, even if an up-to-date copy already exists
+    #[arg(long, global = true)]
+    pub force: bool,
+
+    /// Number of retries after a network error, 429 or 5xx response
+    #[arg(long, global = true, value_name = "N", default_value_t = DEFAULT_RETRIES)]
+    pub retries: u32,
+
+    /// Maximum delay between two attempts in seconds
+    #[arg(long, global = true, value_name = "SECS", default_value = "30", value_parser = parse_seconds)]
+    pub retry_max_delay: Duration,
+
+    /// User agent sent with every request
+    #[arg(long, global = true, value_name = "UA")]
+    pub user_agent: Option<String>,
+
+    /// Connect timeout in seconds [default: 30]
+    #[arg(long, global = true, value_name = "SECS", value_parser = parse_seconds)]
+    pub connect_timeout: Option<Duration>,
+
+    /// Read timeout in seconds [default: 60]
+    #[arg(long, global = true, value_name = "SECS", value_parser = parse_seconds)]
+    pub timeout: Option<Duration>,
+
+    /// HTTP or SOCKS proxy, e.g. socks5://127.0.0.1:1080. Defaults to the HTTP_PROXY,
+    /// HTTPS_PROXY, ALL_PROXY and NO_PROXY environment variables
+    #[arg(long, global = true, value_name = "URL")]
+    pub proxy: Option<String>,
+
+    /// Extra header sent with every request, can be repeated
+    #[arg(short = 'H', long = "header", global = true, value_name = "'NAME: VALUE'", value_parser = parse_header)]
+    pub headers: Vec<(String, String)>,
+
+    /// Cookie header sent with every request, e.g. 'name=value'
+    #[arg(long, global = true, value_name = "COOKIE")]
+    pub cookie: Option<String>,
+}
+
+impl GlobalOptions {
+    pub fn config(&self) -> Result<Config, Box<dyn std::error::Error>> {
+        let mut headers = self.headers.clone();
+        if let Some(cookie) = &self.cookie {
+            headers.push(("Cookie".to_string(), cookie.clone()));
+        }
+
+        let http_options = HttpOptions {
+            user_agent: self.user_agent.clone(),
+            connect_timeout: self.connect_timeout,
+            read_timeout: self.timeout,
+            proxy: self.proxy.clone(),
+            headers,
+        };
+
+        Ok(Config {
+            jobs: self.jobs,
+            client: build_client(&http_options)?,
+            retries: self.retries,
+            retry_max_delay: self.retry_max_delay,
+            force: self.force,
+            output: self.output.clone(),
+            template: self.template.clone(),
+            assets: if self.all_variants {
+                Asset::ALL_VARIANTS.to_vec()
+            } else {
+                self.assets.clone()
+            },
+            source: self.source,
+            sample_text: self.sample_text.clone(),
+            font: self.font.clone(),
+            convert: self.convert,
+            gif_options: GifOptions {
+                dither: self.dither,
+                alpha_threshold: self.alpha_threshold,
+            },
+            webp_quality: self.webp_quality,
+            delete_original: self.delete_original,
+            export: self.export.clone(),
+            mux_sound: self.mux_sound,
+            explode_frames: self.explode_frames,
+            ffmpeg: self.ffmpeg.clone(),
+            cdn_url: CDN_URL.to_string(),
+            lang: self.lang.clone(),
+            dry_run: self.dry_run,
+            verbose: self.verbose,
+        })
+    }
+}
+
+fn parse_jobs(value: &str) -> Result<usize, String> {
+    match value.parse::<usize>() {
+        Ok(jobs) if jobs > 0 => Ok(jobs),
+        _ => Err("expected a number greater than 0".to_string()),
+    }
+}
+
+fn parse_seconds(value: &str) -> Result<Duration, String> {
+    match value.parse::<f64>() {
+        Ok(seconds) if seconds > 0.0 && seconds.is_finite() => Ok(Duration::from_secs_f64(seconds)),
+        _ => Err("expected a number of seconds greater than 0".to_string()),
+    }
+}
+
+fn parse_header(value: &str) -> Result<(String, String), String> {
+    match value.split_once(':') {
+        Some((name, value)) if !name.trim().is_empty() => {
+            Ok((name.trim().to_string(), value.trim().to_string()))
+        }
+        _ => Err("expected the format 'Name: Value'".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn test_cli_definition() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn test_cli_missing_command() {
+        let actual = Cli::try_parse_from(["program_name"]);
+        assert_eq!(
+            actual.unwrap_err().kind(),
+            clap::error::ErrorKind::DisplayHelpOnMissingArgumentOrSubcommand
+        );
+    }
+
+    #[test]
+    fn test_cli_subcommands() {
+        let cli = Cli::try_parse_from(["program_name", "download", "6920150", "683"]).unwrap();
+        assert!(
+            matches!(cli.command, Command::Download { inputs } if inputs == ["6920150", "683"])
+        );
+
+        // A search query that looks like a URL scheme is still a search query.
+        let cli = Cli::try_parse_from(["program_name", "search", "miku:cute"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Command::Search { queries, category: Category::Sticker } if queries == ["miku:cute"]
+        ));
+
+        let cli =
+            Cli::try_parse_from(["program_name", "search", "--category", "emoji", "miku"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Command::Search {
+                category: Category::Emoji,
+                ..
+            }
+        ));
+
+        let cli = Cli::try_parse_from(["program_name", "author", "27290"]).unwrap();
+        assert!(matches!(cli.command, Command::Author { authors } if authors == ["27290"]));
+
+        let cli = Cli::try_parse_from(["program_name", "info", "6920150"]).unwrap();
+        assert!(matches!(cli.command, Command::Info { inputs } if inputs == ["6920150"]));
+
+        let cli = Cli::try_parse_from(["program_name", "completions", "bash"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Command::Completions {
+                shell: clap_complete::Shell::Bash
+            }
+        ));
+
+        assert!(Cli::try_parse_from(["program_name", "download"]).is_err());
+    }
+
+    #[test]
+    fn test_cli_global_options() {
+        let cli = Cli::try_parse_from([
+            "program_name",
+            "--jobs",
+            "8",
+            "search",
+            "hatsune+miku",
+            "--output=stickers",
+            "--lang",
+            "ja",
+            "--dry-run",
+            "-v",
+            "--force",
+            "--retries",
+            "0",
+            "--retry-max-delay=1.5",
+            "--template",
+            "{author}/{title}/{index:02}.{ext}",
+            "--assets=image,popup",
+            "--source",
+            "zip",
+            "--sample-text",
+            "Hello",
+            "--font=font.ttf",
+            "--convert",
+            "gif",
+            "--dither",
+            "--alpha-threshold=1",
+            "--webp-quality",
+            "80",
+            "--delete-original",
+            "--export",
+            "telegram,signal",
+            "--mux-sound=webm",
+            "--explode-frames",
+            "--ffmpeg=/opt/ffmpeg/bin/ffmpeg",
+        ])
+        .unwrap();
+
+        let config = cli.options.config().unwrap();
+        assert_eq!(config.jobs, 8);
+        assert_eq!(config.output, PathBuf::from("stickers"));
+        assert_eq!(config.lang, "ja");
+        assert!(config.dry_run);
+        assert!(config.verbose);
+        assert!(config.force);
+        assert_eq!(config.retries, 0);
+        assert_eq!(config.retry_max_delay, Duration::from_millis(1500));
+        assert_eq!(
+            config.template,
+            PathTemplate::parse("{author}/{title}/{index:02}.{ext}").unwrap()
+        );
+        assert_eq!(config.assets, vec![Asset::Image, Asset::Popup]);
+        assert_eq!(config.source, Source::Zip);
+        assert_eq!(config.sample_text.as_deref(), Some("Hello"));
+        assert_eq!(config.font, Some(PathBuf::from("font.ttf")));
+        assert_eq!(config.convert, Some(Convert::Gif));
+        assert_eq!(
+            config.gif_options,
+            GifOptions {
+                dither: true,
+                alpha_threshold: 1
+            }
+        );
+        assert_eq!(config.webp_quality, 80);
+        assert!(config.delete_original);
+        assert_eq!(config.export, vec![Export::Telegram, Export::Signal]);
+        assert_eq!(config.mux_sound, Some(Mux::Webm));
+        assert!(config.explode_frames);
+        assert_eq!(config.ffmpeg, PathBuf::from("/opt/ffmpeg/bin/ffmpeg"));
+    }
+
+    #[test]
+    fn test_cli_default_options() {
+        let cli = Cli::try_parse_from(["program_name", "search", "hatsune+miku"]).unwrap();
+
+        let config = cli.options.config().unwrap();
+        assert_eq!(config.jobs, DEFAULT_JOBS);
+        assert_eq!(config.output, PathBuf::from("."));
+        assert_eq!(config.lang, "en");
+        assert!(!config.dry_run);
+        assert!(!config.verbose);
+        assert!(!config.force);
+        assert_eq!(config.retries, DEFAULT_RETRIES);
+        assert_eq!(config.convert, None);
+        assert_eq!(config.gif_options, GifOptions::default());
+        assert_eq!(config.webp_quality, DEFAULT_WEBP_QUALITY);
+        assert!(!config.delete_original);
+        assert!(config.export.is_empty());
+        assert_eq!(config.mux_sound, None);
+        assert!(!config.explode_frames);
+        assert_eq!(config.ffmpeg, PathBuf::from("ffmpeg"));
+        assert_eq!(
+            config.assets,
+            vec![
+                Asset::Image,
+                Asset::Sound,
+                Asset::Popup,
+                Asset::Overlay,
+                Asset::Package
+            ]
+        );
+    }
+
+    #[test]
+    fn test_cli_all_variants() {
+        let cli =
+            Cli::try_parse_from(["program_name", "download", "683", "--all-variants"]).unwrap();
+
+        let config = cli.options.config().unwrap();
+        assert_eq!(config.assets, Asset::ALL_VARIANTS);
+
+        let cli = Cli::try_parse_from([
+            "program_name",
+            "download",
+            "683",
+            "--assets=fallback-static",
+        ])
+        .unwrap();
+        assert_eq!(cli.options.assets, vec![Asset::FallbackStatic]);
+    }
+
+    #[test]
+    fn test_cli_http_options() {
+        let cli = Cli::try_parse_from([
+            "program_name",
+            "search",
+            "hatsune+miku",
+            "--user-agent",
+            "Mozilla/5.0",
+            "--connect-timeout=5",
+            "--timeout",
+            "2.5",
+            "--proxy",
+            "socks5://127.0.0.1:1080",
+            "--header",
+            "Accept-Language: ja",
+            "--cookie",
+            "session=abc",
+        ])
+        .unwrap();
+
+        assert_eq!(
+            cli.options.headers,
+            vec![("Accept-Language".to_string(), "ja".to_string())]
+        );
+        assert!(cli.options.config().is_ok());
+    }
+
+    #[test]
+    fn test_cli_invalid_options() {
+        assert!(Cli::try_parse_from(["program_name", "search", "q", "--jobs=0"]).is_err());
+        assert!(Cli::try_parse_from(["program_name", "search", "q", "-j", "many"]).is_err());
+        assert!(Cli::try_parse_from(["program_name", "search", "q", "--retries=-1"]).is_err());
+        assert!(Cli::try_parse_from(["program_name", "search", "q", "--timeout=0"]).is_err());
+        assert!(
+            Cli::try_parse_from(["program_name", "search", "q", "--connect-timeout=soon"]).is_err()
+        );
+        assert!(Cli::try_parse_from(["program_name", "search", "q", "--header=NoColon"]).is_err());
+        assert!(Cli::try_parse_from(["program_name", "search", "q", "--template={name}"]).is_err());
+        assert!(Cli::try_parse_from(["program_name", "search", "q", "--assets=video"]).is_err());
+        assert!(Cli::try_parse_from(["program_name", "search", "q", "--source=api"]).is_err());
+        assert!(Cli::try_parse_from(["program_name", "search", "q", "--font=font.ttf"]).is_err());
+        assert!(Cli::try_parse_from(["program_name", "search", "q", "--convert=mp4"]).is_err());
+        assert!(Cli::try_parse_from(["program_name", "search", "q", "--dither"]).is_err());
+        assert!(
+            Cli::try_parse_from(["program_name", "search", "q", "--alpha-threshold=5"]).is_err()
+        );
+        assert!(Cli::try_parse_from(["program_name", "search", "q", "--delete-original"]).is_err());
+        assert!(
+            Cli::try_parse_from(["program_name", "search", "q", "--webp-quality=101"]).is_err()
+        );
+        assert!(Cli::try_parse_from(["program_name", "search", "q", "--export=line"]).is_err());
+        assert!(Cli::try_parse_from(["program_name", "search", "q", "--mux-sound=gif"]).is_err());
+        assert!(
+            Cli::try_parse_from(["program_name", "search", "q", "--alpha-threshold=256"]).is_err()
+        );
+        assert!(Cli::try_parse_from([
+            "program_name",
+            "search",
+            "q",
+            "--assets=image",
+            "--all-variants"
+        ])
+        .is_err());
+
+        let cli =
+            Cli::try_parse_from(["program_name", "search", "q", "--proxy=not a proxy"]).unwrap();
+        assert!(cli.options.config().is_err());
+    }
+
+    #[test]
+    fn test_parse_header() {
+        let actual = parse_header("Cookie: a=b; c=d").unwrap();
+        assert_eq!(actual, ("Cookie".to_string(), "a=b; c=d".to_string()));
+
+        let actual = parse_header("X-Empty:").unwrap();
+        assert_eq!(actual, ("X-Empty".to_string(), "".to_string()));
+
+        assert!(parse_header(": value").is_err());
+        assert!(parse_header("value").is_err());
+    }
+}
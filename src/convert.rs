@@ -0,0 +1,896 @@
+use color_quant::NeuQuant;
+use image::codecs::png::PngDecoder;
+use image::{AnimationDecoder, Rgba, RgbaImage};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+
+use crate::{write_file_atomically, Config, Convert};
+
+pub const DEFAULT_ALPHA_THRESHOLD: u8 = 128;
+pub const DEFAULT_WEBP_QUALITY: u8 = 100;
+
+/// Browsers play GIF frames with a delay below 20 ms at 100 ms, so shorter delays are
+/// rounded up to this many hundredths of a second.
+const MIN_GIF_DELAY: u16 = 2;
+
+const WEBP_ANIMATION_FLAG: u8 = 0x02;
+const WEBP_ALPHA_FLAG: u8 = 0x10;
+const WEBP_NO_BLEND_FLAG: u8 = 0x02;
+const WEBP_MAX_DURATION: u32 = 0xFF_FFFF;
+
+/// Sample factor of the NeuQuant quantizer, 10 is its recommended trade-off between
+/// speed and quality.
+const QUANTIZER_SAMPLE_FACTOR: i32 = 10;
+
+/// How the frames of an animation are reduced to the 1-bit transparency and 256 colors
+/// of a GIF.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GifOptions {
+    /// Spread the error of the reduced palette over neighbouring pixels.
+    pub dither: bool,
+    /// Pixels with a lower alpha become transparent, all others opaque.
+    pub alpha_threshold: u8,
+}
+
+impl Default for GifOptions {
+    fn default() -> Self {
+        GifOptions {
+            dither: false,
+            alpha_threshold: DEFAULT_ALPHA_THRESHOLD,
+        }
+    }
+}
+
+/// Returns the path an animation is converted to, e.g. `01_123.gif` for `01_123.png`.
+pub fn converted_path(path: &Path, format: Convert) -> PathBuf {
+    path.with_extension(format.extension())
+}
+
+/// Converts the PNGs among `paths` into `format`, next to the original file. GIF
+/// conversion leaves static images alone. Returns the converted file of every converted path.
+pub fn convert_files<'a>(
+    config: &Config,
+    format: Convert,
+    paths: impl Iterator<Item = &'a PathBuf>,
+) -> Result<HashMap<PathBuf, PathBuf>, Box<dyn std::error::Error>> {
+    let mut converted = HashMap::new();
+    for path in paths {
+        if let Some(output_path) = convert_file(config, format, path)? {
+            converted.insert(path.clone(), output_path);
+        }
+    }
+
+    Ok(converted)
+}
+
+/// Returns the files a downloaded file ended up as: the file itself, unless it was
+/// deleted after converting it, followed by its conversion.
+pub fn output_files<'a>(
+    config: &Config,
+    converted: &'a HashMap<PathBuf, PathBuf>,
+    path: &'a PathBuf,
+) -> Vec<&'a PathBuf> {
+    match converted.get(path) {
+        Some(output_path) if config.delete_original => vec![output_path],
+        Some(output_path) => vec![path, output_path],
+        None => vec![path],
+    }
+}
+
+/// Converts a single file, returning `Ok(None)` if it is not a PNG or, for GIF, not animated.
+pub fn convert_file(
+    config: &Config,
+    format: Convert,
+    path: &Path,
+) -> Result<Option<PathBuf>, Box<dyn std::error::Error>> {
+    if path.extension().and_then(|ext| ext.to_str()) != Some("png") {
+        return Ok(None);
+    }
+    let data = std::fs::read(path)?;
+    let plays = animation_plays(&data);
+    if format == Convert::Gif && plays.is_none() {
+        return Ok(None);
+    }
+
+    let output_path = converted_path(path, format);
+    if !config.force && is_converted(path, &output_path) {
+        if config.verbose {
+            println!("Skipping {} (up to date)", output_path.display());
+        }
+    } else {
+        let converted = match (format, plays) {
+            (Convert::Gif, plays) => encode_gif(&data, plays.unwrap_or(1), config.gif_options),
+            (Convert::Webp, Some(plays)) => encode_animated_webp(&data, plays, config.webp_quality),
+            (Convert::Webp, None) => encode_webp(&data, config.webp_quality),
+        }
+        .map_err(|err| format!("Could not convert {}: {}", path.display(), err))?;
+        write_file_atomically(&output_path, &converted)?;
+        if config.verbose {
+            println!("Converted {}", output_path.display());
+        }
+    }
+
+    if config.delete_original {
+        std::fs::remove_file(path)?;
+    }
+
+    Ok(Some(output_path))
+}
+
+/// A conversion is up to date if it was written after the file it was converted from.
+pub fn is_converted(path: &Path, output_path: &Path) -> bool {
+    let modified = |path: &Path| std::fs::metadata(path).and_then(|metadata| metadata.modified());
+    match (modified(path), modified(output_path)) {
+        (Ok(source), Ok(output)) => output >= source,
+        _ => false,
+    }
+}
+
+/// Returns how often an APNG is played, `0` meaning forever, or `None` if the file is
+/// not an animated PNG. Only the `acTL` chunk in front of the image data is looked at.
+pub fn animation_plays(png: &[u8]) -> Option<u32> {
+    let mut chunks = png.strip_prefix(b"\x89PNG\r\n\x1a\n")?;
+    while chunks.len() >= 8 {
+        let length = u32::from_be_bytes(chunks[0..4].try_into().ok()?) as usize;
+        let chunk_type = &chunks[4..8];
+        let data = chunks.get(8..8 + length)?;
+        match chunk_type {
+            b"acTL" => return Some(u32::from_be_bytes(data.get(4..8)?.try_into().ok()?)),
+            b"IDAT" => return None,
+            _ => chunks = chunks.get(12 + length..)?,
+        }
+    }
+
+    None
+}
+
+/// Re-encodes an APNG as a GIF that is played `plays` times, `0` meaning forever.
+///
+/// Every frame gets its own palette. Consecutive frames that look the same after the
+/// alpha threshold is applied are merged into one frame showing for their combined delay.
+pub fn encode_gif(
+    png: &[u8],
+    plays: u32,
+    options: GifOptions,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    write_gif(decode_apng(png)?, plays, options)
+}
+
+/// Encodes frames of the same size as a GIF that is played `plays` times, `0` meaning forever.
+///
+/// Every frame after the first one only holds the pixels that changed since the previous
+/// frame, cropped to their bounding box, unless it makes pixels transparent.
+pub fn write_gif(
+    mut frames: Vec<(RgbaImage, f64)>,
+    plays: u32,
+    options: GifOptions,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let (width, height) = frames
+        .first()
+        .ok_or("Animation has no frames")?
+        .0
+        .dimensions();
+    let (Ok(width), Ok(height)) = (u16::try_from(width), u16::try_from(height)) else {
+        return Err(format!("{}x{} is too large for a GIF", width, height).into());
+    };
+    for (image, _) in &mut frames {
+        apply_alpha_threshold(image, options.alpha_threshold);
+    }
+
+    let mut gif = Vec::new();
+    {
+        let mut encoder = gif::Encoder::new(&mut gif, width, height, &[])?;
+        // An APNG counts every play, a GIF only the repetitions after the first one.
+        match plays {
+            0 => encoder.set_repeat(gif::Repeat::Infinite)?,
+            1 => {}
+            plays => encoder.set_repeat(gif::Repeat::Finite(
+                u16::try_from(plays - 1).unwrap_or(u16::MAX),
+            ))?,
+        }
+
+        let frames = merge_frames(frames);
+        // Frames are drawn over the previous one, which cannot make a pixel transparent
+        // again. The frame before one that does is drawn in full and cleared afterwards.
+        let clears: Vec<bool> = (0..frames.len())
+            .map(|index| index > 0 && clears_pixels(&frames[index - 1].0, &frames[index].0))
+            .collect();
+        for (index, (image, delay_ms)) in frames.iter().enumerate() {
+            let clears_next = clears.get(index + 1).copied().unwrap_or(false);
+            let mut frame = if index == 0 || clears[index] || clears_next {
+                quantize(image, options.dither)
+            } else {
+                let (left, top, region) = changed_region(&frames[index - 1].0, image);
+                let mut frame = quantize(&region, options.dither);
+                frame.left = left as u16;
+                frame.top = top as u16;
+                frame
+            };
+            frame.delay = gif_delay(*delay_ms);
+            frame.dispose = if clears_next {
+                gif::DisposalMethod::Background
+            } else {
+                gif::DisposalMethod::Keep
+            };
+            encoder.write_frame(&frame)?;
+        }
+    }
+
+    Ok(gif)
+}
+
+/// Re-encodes a static PNG as a WebP, see [`reduce_precision`] for `quality`.
+pub fn encode_webp(png: &[u8], quality: u8) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let mut image = image::load_from_memory_with_format(png, image::ImageFormat::Png)?.to_rgba8();
+    reduce_precision(&mut image, quality);
+    write_webp(&image)
+}
+
+/// Re-encodes an APNG as an animated WebP that is played `plays` times, `0` meaning forever.
+/// Consecutive frames that look the same are merged like for GIFs.
+pub fn encode_animated_webp(
+    png: &[u8],
+    plays: u32,
+    quality: u8,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let mut frames = decode_apng(png)?;
+    for (image, _) in &mut frames {
+        reduce_precision(image, quality);
+    }
+    write_animated_webp(&merge_frames(frames), plays)
+}
+
+/// Decodes the frames of an APNG as complete images, each with its delay in milliseconds.
+pub fn decode_apng(png: &[u8]) -> Result<Vec<(RgbaImage, f64)>, image::ImageError> {
+    PngDecoder::new(Cursor::new(png))?
+        .apng()?
+        .into_frames()
+        .map(|frame| {
+            let frame = frame?;
+            let (numerator, denominator) = frame.delay().numer_denom_ms();
+            Ok((frame.into_buffer(), numerator as f64 / denominator as f64))
+        })
+        .collect()
+}
+
+/// Merges consecutive frames that look the same into one frame showing for their combined delay.
+pub fn merge_frames(frames: Vec<(RgbaImage, f64)>) -> Vec<(RgbaImage, f64)> {
+    let mut merged_frames: Vec<(RgbaImage, f64)> = Vec::new();
+    for (image, delay_ms) in frames {
+        match merged_frames.last_mut() {
+            Some((previous, previous_delay_ms)) if *previous == image => {
+                *previous_delay_ms += delay_ms
+            }
+            _ => merged_frames.push((image, delay_ms)),
+        }
+    }
+
+    merged_frames
+}
+
+/// Encodes an image as a lossless WebP.
+pub fn write_webp(image: &RgbaImage) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let mut webp = Vec::new();
+    image_webp::WebPEncoder::new(&mut webp).encode(
+        image.as_raw(),
+        image.width(),
+        image.height(),
+        image_webp::ColorType::Rgba8,
+    )?;
+    Ok(webp)
+}
+
+/// Encodes frames of the same size as an animated WebP.
+///
+/// There is no pure Rust encoder for animated WebPs, so every frame is encoded as a still
+/// image and the animation container is written around them.
+pub fn write_animated_webp(
+    frames: &[(RgbaImage, f64)],
+    plays: u32,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let (width, height) = frames
+        .first()
+        .ok_or("Animation has no frames")?
+        .0
+        .dimensions();
+    let has_alpha = frames
+        .iter()
+        .any(|(image, _)| image.pixels().any(|pixel| pixel[3] != 255));
+    let mut chunks = Vec::new();
+
+    let mut vp8x = vec![
+        WEBP_ANIMATION_FLAG | if has_alpha { WEBP_ALPHA_FLAG } else { 0 },
+        0,
+        0,
+        0,
+    ];
+    vp8x.extend(&u24_le(width - 1)?);
+    vp8x.extend(&u24_le(height - 1)?);
+    write_riff_chunk(&mut chunks, b"VP8X", &vp8x);
+
+    // Transparent background, then the loop count which counts every play like an APNG.
+    let mut anim = vec![0, 0, 0, 0];
+    anim.extend(&u16::try_from(plays).unwrap_or(u16::MAX).to_le_bytes());
+    write_riff_chunk(&mut chunks, b"ANIM", &anim);
+
+    for (image, delay_ms) in frames {
+        let still = write_webp(image)?;
+
+        // Frames cover the whole canvas at 0,0 and replace the previous frame instead of
+        // being blended with it.
+        let mut anmf = vec![0; 6];
+        anmf.extend(&u24_le(width - 1)?);
+        anmf.extend(&u24_le(height - 1)?);
+        anmf.extend(&u24_le(
+            delay_ms.round().min(WEBP_MAX_DURATION as f64) as u32
+        )?);
+        anmf.push(WEBP_NO_BLEND_FLAG);
+        // The still image is a RIFF header followed by its VP8L chunk.
+        anmf.extend(&still[12..]);
+        write_riff_chunk(&mut chunks, b"ANMF", &anmf);
+    }
+
+    let mut webp = b"RIFF".to_vec();
+    webp.extend(&(chunks.len() as u32 + 4).to_le_bytes());
+    webp.extend(b"WEBP");
+    webp.extend(chunks);
+    Ok(webp)
+}
+
+/// Drops low bits of the color channels so that lossless WebP compresses them better, in
+/// the spirit of libwebp's near-lossless mode. `100` keeps every bit, every 20 below it
+/// drop one more, up to 5 bits at `0`. Alpha is always kept.
+pub fn reduce_precision(image: &mut RgbaImage, quality: u8) {
+    let bits = (100 - quality.min(100) as u32).div_ceil(20);
+    if bits == 0 {
+        return;
+    }
+
+    let half = 1 << (bits - 1);
+    for pixel in image.pixels_mut() {
+        for channel in &mut pixel.0[0..3] {
+            *channel = ((((*channel as u32 + half) >> bits) << bits).min(255)) as u8;
+        }
+    }
+}
+
+fn u24_le(value: u32) -> Result<[u8; 3], String> {
+    if value > 0xFF_FFFF {
+        return Err(format!("{} does not fit into a WebP header", value));
+    }
+    let [a, b, c, _] = value.to_le_bytes();
+    Ok([a, b, c])
+}
+
+fn write_riff_chunk(buffer: &mut Vec<u8>, name: &[u8; 4], data: &[u8]) {
+    buffer.extend(name);
+    buffer.extend(&(data.len() as u32).to_le_bytes());
+    buffer.extend(data);
+    if data.len() % 2 == 1 {
+        buffer.push(0);
+    }
+}
+
+fn apply_alpha_threshold(image: &mut RgbaImage, threshold: u8) {
+    for pixel in image.pixels_mut() {
+        pixel[3] = if pixel[3] >= threshold { 255 } else { 0 };
+    }
+}
+
+/// Whether a pixel that is visible in `previous` is transparent in `image`.
+fn clears_pixels(previous: &RgbaImage, image: &RgbaImage) -> bool {
+    previous
+        .pixels()
+        .zip(image.pixels())
+        .any(|(previous, pixel)| previous[3] != 0 && pixel[3] == 0)
+}
+
+/// Crops a frame to the bounding box of the pixels that changed since the previous frame
+/// and makes the unchanged ones transparent, so the previous frame shows through them.
+/// Returns the cropped frame with its position.
+fn changed_region(previous: &RgbaImage, image: &RgbaImage) -> (u32, u32, RgbaImage) {
+    let changed = |x, y| {
+        let (previous, pixel) = (previous.get_pixel(x, y), image.get_pixel(x, y));
+        previous != pixel && (previous[3] != 0 || pixel[3] != 0)
+    };
+    let mut bounds: Option<(u32, u32, u32, u32)> = None;
+    for (x, y, _) in image.enumerate_pixels() {
+        if changed(x, y) {
+            bounds = Some(match bounds {
+                Some((left, top, right, bottom)) => {
+                    (left.min(x), top.min(y), right.max(x), bottom.max(y))
+                }
+                None => (x, y, x, y),
+            });
+        }
+    }
+    let Some((left, top, right, bottom)) = bounds else {
+        // A GIF frame cannot be empty, a transparent pixel changes nothing.
+        return (0, 0, RgbaImage::new(1, 1));
+    };
+
+    let region = RgbaImage::from_fn(right - left + 1, bottom - top + 1, |x, y| {
+        if changed(left + x, top + y) {
+            *image.get_pixel(left + x, top + y)
+        } else {
+            Rgba([0, 0, 0, 0])
+        }
+    });
+    (left, top, region)
+}
+
+/// Converts a delay in milliseconds to the hundredths of a second of a GIF frame.
+fn gif_delay(delay_ms: f64) -> u16 {
+    let delay = (delay_ms / 10.0).round();
+    if delay >= u16::MAX as f64 {
+        u16::MAX
+    } else {
+        (delay as u16).max(MIN_GIF_DELAY)
+    }
+}
+
+/// Reduces a frame whose pixels are either opaque or transparent to an indexed GIF
+/// frame. The color after the last palette entry is used for transparency.
+fn quantize(image: &RgbaImage, dither: bool) -> gif::Frame<'static> {
+    let colors: BTreeSet<[u8; 3]> = image
+        .pixels()
+        .filter(|pixel| pixel[3] != 0)
+        .map(|pixel| [pixel[0], pixel[1], pixel[2]])
+        .collect();
+
+    let (mut palette, buffer) = if colors.len() < 256 {
+        // Few enough colors to keep them all, which leaves nothing to dither.
+        let transparent = colors.len() as u8;
+        let indices: BTreeMap<[u8; 3], u8> = colors.iter().copied().zip(0..).collect();
+        let buffer = image
+            .pixels()
+            .map(|pixel| match pixel[3] {
+                0 => transparent,
+                _ => indices[&[pixel[0], pixel[1], pixel[2]]],
+            })
+            .collect();
+        (colors.into_iter().flatten().collect::<Vec<u8>>(), buffer)
+    } else {
+        let opaque_pixels: Vec<u8> = image
+            .pixels()
+            .filter(|pixel| pixel[3] != 0)
+            .flat_map(|pixel| pixel.0)
+            .collect();
+        let quantizer = NeuQuant::new(QUANTIZER_SAMPLE_FACTOR, 255, &opaque_pixels);
+        let buffer = map_pixels(image, &quantizer, dither);
+        (quantizer.color_map_rgb(), buffer)
+    };
+
+    let transparent = (palette.len() / 3) as u8;
+    palette.extend([0, 0, 0]);
+    gif::Frame {
+        width: image.width() as u16,
+        height: image.height() as u16,
+        buffer: Cow::Owned(buffer),
+        palette: Some(palette),
+        transparent: Some(transparent),
+        ..gif::Frame::default()
+    }
+}
+
+/// Maps every pixel to the closest color of a 255 color palette, optionally with
+/// Floyd-Steinberg dithering. Transparent pixels are mapped to index 255.
+fn map_pixels(image: &RgbaImage, quantizer: &NeuQuant, dither: bool) -> Vec<u8> {
+    let (width, height) = (image.width() as usize, image.height() as usize);
+    let mut errors = vec![[0f32; 3]; if dither { width * height } else { 0 }];
+    let mut buffer = Vec::with_capacity(width * height);
+
+    for (position, pixel) in image.pixels().enumerate() {
+        if pixel[3] == 0 {
+            buffer.push(255);
+            continue;
+        }
+
+        let color = match errors.get(position) {
+            Some(error) => [0, 1, 2].map(|c| (pixel[c] as f32 + error[c]).clamp(0.0, 255.0)),
+            None => [0, 1, 2].map(|c| pixel[c] as f32),
+        };
+        let [r, g, b] = color.map(|c| c.round() as u8);
+        let index = quantizer.index_of(&[r, g, b, 255]);
+        buffer.push(index as u8);
+
+        if !dither {
+            continue;
+        }
+        let chosen = quantizer.lookup(index).unwrap_or([r, g, b, 255]);
+        let error = [0, 1, 2].map(|c| color[c] - chosen[c] as f32);
+        let (x, y) = (position % width, position / width);
+        for (dx, dy, weight) in [
+            (1, 0, 7.0 / 16.0),
+            (-1, 1, 3.0 / 16.0),
+            (0, 1, 5.0 / 16.0),
+            (1, 1, 1.0 / 16.0),
+        ] {
+            let (Some(nx), ny) = (x.checked_add_signed(dx), y + dy) else {
+                continue;
+            };
+            if nx < width && ny < height {
+                let neighbour = &mut errors[ny * width + nx];
+                for c in 0..3 {
+                    neighbour[c] += error[c] * weight;
+                }
+            }
+        }
+    }
+
+    buffer
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Encodes RGBA frames as an APNG, each shown for `delay_ms`.
+    pub(crate) fn apng(width: u32, height: u32, frames: &[(Vec<u8>, u16)], plays: u32) -> Vec<u8> {
+        let mut data = Vec::new();
+        let mut encoder = png::Encoder::new(&mut data, width, height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.set_animated(frames.len() as u32, plays).unwrap();
+        let mut writer = encoder.write_header().unwrap();
+        for (pixels, delay_ms) in frames {
+            writer.set_frame_delay(*delay_ms, 1000).unwrap();
+            writer.write_image_data(pixels).unwrap();
+        }
+        writer.finish().unwrap();
+        data
+    }
+
+    fn fill(width: u32, height: u32, pixel: [u8; 4]) -> Vec<u8> {
+        pixel.repeat((width * height) as usize)
+    }
+
+    /// Decodes a GIF into its loop count and RGBA frames with their delays.
+    fn decode_gif(data: &[u8]) -> (gif::Repeat, Vec<(Vec<u8>, u16)>) {
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::RGBA);
+        let mut decoder = options.read_info(Cursor::new(data)).unwrap();
+        let mut frames = Vec::new();
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            frames.push((frame.buffer.to_vec(), frame.delay));
+        }
+        (decoder.repeat(), frames)
+    }
+
+    #[test]
+    fn test_animation_plays() {
+        let frames = [(fill(2, 2, [255, 0, 0, 255]), 100)];
+        assert_eq!(animation_plays(&apng(2, 2, &frames, 0)), Some(0));
+        assert_eq!(animation_plays(&apng(2, 2, &frames, 3)), Some(3));
+
+        let mut png = Cursor::new(Vec::new());
+        RgbaImage::new(2, 2)
+            .write_to(&mut png, image::ImageFormat::Png)
+            .unwrap();
+        assert_eq!(animation_plays(png.get_ref()), None);
+        assert_eq!(animation_plays(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR"), None);
+        assert_eq!(animation_plays(b"GIF89a"), None);
+    }
+
+    #[test]
+    fn test_gif_delay() {
+        assert_eq!(gif_delay(100.0), 10);
+        assert_eq!(gif_delay(44.0), 4);
+        assert_eq!(gif_delay(45.0), 5);
+        assert_eq!(gif_delay(0.0), MIN_GIF_DELAY);
+        assert_eq!(gif_delay(1e9), u16::MAX);
+    }
+
+    #[test]
+    fn test_encode_gif() {
+        let mut first = fill(4, 2, [255, 0, 0, 255]);
+        // Transparent left column.
+        for y in 0..2 {
+            first[y * 16 + 3] = 0;
+        }
+        let second = fill(4, 2, [0, 0, 255, 255]);
+        let frames = [
+            (first.clone(), 100),
+            (first.clone(), 50),
+            (second.clone(), 30),
+        ];
+
+        let gif = encode_gif(&apng(4, 2, &frames, 0), 0, GifOptions::default()).unwrap();
+        let (repeat, frames) = decode_gif(&gif);
+        assert_eq!(repeat, gif::Repeat::Infinite);
+        assert_eq!(frames.len(), 2, "identical frames are merged");
+        assert_eq!(frames[0].1, 15);
+        assert_eq!(frames[1].1, 3);
+        assert_eq!(&frames[0].0[0..4], [0, 0, 0, 0]);
+        assert_eq!(&frames[0].0[4..8], [255, 0, 0, 255]);
+        assert_eq!(frames[1].0, second);
+    }
+
+    #[test]
+    fn test_write_gif_changed_regions() {
+        let red = Rgba([255, 0, 0, 255]);
+        let first = RgbaImage::from_pixel(4, 4, red);
+        let mut second = first.clone();
+        second.put_pixel(2, 1, Rgba([0, 0, 255, 255]));
+        let mut third = second.clone();
+        third.put_pixel(0, 0, Rgba([0, 0, 0, 0]));
+        let mut fourth = third.clone();
+        fourth.put_pixel(3, 3, Rgba([0, 255, 0, 255]));
+        fourth.put_pixel(1, 2, Rgba([0, 255, 0, 255]));
+        let images = [first, second, third, fourth];
+        let frames = images.iter().map(|image| (image.clone(), 100.0)).collect();
+        let gif = write_gif(frames, 0, GifOptions::default()).unwrap();
+
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::RGBA);
+        let mut decoder = options.read_info(Cursor::new(gif)).unwrap();
+        let mut canvas = RgbaImage::new(4, 4);
+        let mut regions = Vec::new();
+        let mut shown = Vec::new();
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            let (left, top) = (frame.left as u32, frame.top as u32);
+            let (width, height) = (frame.width as u32, frame.height as u32);
+            let region = RgbaImage::from_raw(width, height, frame.buffer.to_vec()).unwrap();
+            for (x, y, pixel) in region.enumerate_pixels() {
+                if pixel[3] != 0 {
+                    canvas.put_pixel(left + x, top + y, *pixel);
+                }
+            }
+            shown.push(canvas.clone());
+            if frame.dispose == gif::DisposalMethod::Background {
+                for (x, y) in (0..width).flat_map(|x| (0..height).map(move |y| (x, y))) {
+                    canvas.put_pixel(left + x, top + y, Rgba([0, 0, 0, 0]));
+                }
+            }
+            regions.push((left, top, width, height, frame.dispose));
+        }
+
+        assert_eq!(shown, images);
+        assert_eq!(
+            regions,
+            [
+                (0, 0, 4, 4, gif::DisposalMethod::Keep),
+                // Drawn in full, the next frame has to clear it.
+                (0, 0, 4, 4, gif::DisposalMethod::Background),
+                (0, 0, 4, 4, gif::DisposalMethod::Keep),
+                (1, 2, 3, 2, gif::DisposalMethod::Keep),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_changed_region() {
+        let previous = RgbaImage::from_pixel(3, 3, Rgba([255, 0, 0, 255]));
+        let mut image = previous.clone();
+        image.put_pixel(1, 1, Rgba([0, 0, 255, 255]));
+        image.put_pixel(2, 2, Rgba([0, 0, 255, 255]));
+        let (left, top, region) = changed_region(&previous, &image);
+        assert_eq!((left, top), (1, 1));
+        assert_eq!(region.dimensions(), (2, 2));
+        assert_eq!(region.get_pixel(0, 0).0, [0, 0, 255, 255]);
+        assert_eq!(region.get_pixel(1, 0).0, [0, 0, 0, 0]);
+
+        let (_, _, region) = changed_region(&previous, &previous);
+        assert_eq!(region.dimensions(), (1, 1));
+        assert_eq!(region.get_pixel(0, 0)[3], 0);
+    }
+
+    #[test]
+    fn test_encode_gif_plays() {
+        let frames = [
+            (fill(1, 1, [255, 0, 0, 255]), 100),
+            (fill(1, 1, [0, 255, 0, 255]), 100),
+        ];
+        let data = apng(1, 1, &frames, 3);
+
+        let (repeat, _) = decode_gif(&encode_gif(&data, 3, GifOptions::default()).unwrap());
+        assert_eq!(repeat, gif::Repeat::Finite(2));
+
+        let (repeat, _) = decode_gif(&encode_gif(&data, 1, GifOptions::default()).unwrap());
+        assert_eq!(repeat, gif::Repeat::Finite(0));
+    }
+
+    #[test]
+    fn test_encode_gif_alpha_threshold() {
+        let frames = [
+            (fill(1, 1, [255, 0, 0, 100]), 100),
+            (fill(1, 1, [0, 255, 0, 255]), 100),
+        ];
+        let data = apng(1, 1, &frames, 0);
+
+        let gif = encode_gif(&data, 0, GifOptions::default()).unwrap();
+        assert_eq!(decode_gif(&gif).1[0].0, [0, 0, 0, 0]);
+
+        let options = GifOptions {
+            alpha_threshold: 50,
+            ..GifOptions::default()
+        };
+        let gif = encode_gif(&data, 0, options).unwrap();
+        assert_eq!(decode_gif(&gif).1[0].0, [255, 0, 0, 255]);
+    }
+
+    #[test]
+    fn test_encode_gif_many_colors() {
+        // A gradient with more colors than a GIF palette holds.
+        let gradient: Vec<u8> = (0..32u32)
+            .flat_map(|y| (0..32u32).flat_map(move |x| [x as u8 * 8, y as u8 * 8, 128, 255]))
+            .collect();
+        let frames = [(gradient.clone(), 100), (fill(32, 32, [0, 0, 0, 0]), 100)];
+        let data = apng(32, 32, &frames, 0);
+
+        for dither in [false, true] {
+            let options = GifOptions {
+                dither,
+                ..GifOptions::default()
+            };
+            let (_, frames) = decode_gif(&encode_gif(&data, 0, options).unwrap());
+            assert_eq!(frames.len(), 2);
+            assert!(frames[0].0.chunks(4).all(|pixel| pixel[3] == 255));
+            let error: u64 = frames[0]
+                .0
+                .iter()
+                .zip(&gradient)
+                .map(|(a, b)| a.abs_diff(*b) as u64)
+                .sum();
+            assert!(error / gradient.len() as u64 <= 8, "dither: {}", dither);
+            assert!(frames[1].0.chunks(4).all(|pixel| pixel[3] == 0));
+        }
+    }
+
+    /// Decodes a WebP into its loop count and RGBA frames with their durations.
+    fn decode_webp(data: &[u8]) -> (image_webp::LoopCount, Vec<(Vec<u8>, u32)>) {
+        let mut decoder = image_webp::WebPDecoder::new(Cursor::new(data)).unwrap();
+        assert!(decoder.has_alpha());
+        let mut frames = Vec::new();
+        for _ in 0..decoder.num_frames() {
+            let mut buffer = vec![0; decoder.output_buffer_size().unwrap()];
+            let duration = decoder.read_frame(&mut buffer).unwrap();
+            frames.push((buffer, duration));
+        }
+        (decoder.loop_count(), frames)
+    }
+
+    #[test]
+    fn test_encode_webp() {
+        let pixels = [255, 0, 0, 255, 0, 255, 0, 128, 0, 0, 255, 0, 1, 2, 3, 255];
+        let mut png = Cursor::new(Vec::new());
+        RgbaImage::from_raw(2, 2, pixels.to_vec())
+            .unwrap()
+            .write_to(&mut png, image::ImageFormat::Png)
+            .unwrap();
+
+        let webp = encode_webp(png.get_ref(), DEFAULT_WEBP_QUALITY).unwrap();
+        let mut decoder = image_webp::WebPDecoder::new(Cursor::new(webp)).unwrap();
+        assert!(!decoder.is_animated());
+        let mut buffer = vec![0; decoder.output_buffer_size().unwrap()];
+        decoder.read_image(&mut buffer).unwrap();
+        assert_eq!(buffer, pixels);
+    }
+
+    #[test]
+    fn test_encode_animated_webp() {
+        let mut first = fill(3, 2, [255, 0, 0, 255]);
+        first[3] = 0;
+        let second = fill(3, 2, [0, 0, 255, 200]);
+        let frames = [
+            (first.clone(), 100),
+            (first.clone(), 50),
+            (second.clone(), 30),
+        ];
+        let data = apng(3, 2, &frames, 0);
+
+        let webp = encode_animated_webp(&data, 0, DEFAULT_WEBP_QUALITY).unwrap();
+        let (loop_count, frames) = decode_webp(&webp);
+        assert_eq!(loop_count, image_webp::LoopCount::Forever);
+        assert_eq!(frames.len(), 2, "identical frames are merged");
+        assert_eq!(frames[0].1, 150);
+        assert_eq!(frames[1].1, 30);
+        assert_eq!(&frames[0].0[0..4], [255, 0, 0, 0]);
+        assert_eq!(&frames[0].0[4..], &first[4..]);
+        assert_eq!(frames[1].0, second);
+
+        let webp = encode_animated_webp(&data, 3, DEFAULT_WEBP_QUALITY).unwrap();
+        assert_eq!(
+            decode_webp(&webp).0,
+            image_webp::LoopCount::Times(3.try_into().unwrap())
+        );
+    }
+
+    #[test]
+    fn test_reduce_precision() {
+        let pixels = vec![255, 3, 16, 7, 100, 101, 102, 255];
+        let mut image = RgbaImage::from_raw(2, 1, pixels.clone()).unwrap();
+        reduce_precision(&mut image, 100);
+        assert_eq!(image.as_raw(), &pixels);
+
+        reduce_precision(&mut image, 80);
+        assert_eq!(image.as_raw(), &[255, 4, 16, 7, 100, 102, 102, 255]);
+
+        let mut image = RgbaImage::from_raw(2, 1, pixels).unwrap();
+        reduce_precision(&mut image, 0);
+        assert_eq!(image.as_raw(), &[255, 0, 32, 7, 96, 96, 96, 255]);
+    }
+
+    #[test]
+    fn test_convert_file_webp_delete_original() {
+        let directory = Path::new("Test Convert File WebP");
+        std::fs::create_dir_all(directory).unwrap();
+        let animation_path = directory.join("01_1.png");
+        let static_path = directory.join("02_2.png");
+        let frames = [
+            (fill(2, 2, [255, 0, 0, 255]), 100),
+            (fill(2, 2, [0, 255, 0, 128]), 100),
+        ];
+        std::fs::write(&animation_path, apng(2, 2, &frames, 0)).unwrap();
+        RgbaImage::new(2, 2).save(&static_path).unwrap();
+
+        let config = Config {
+            delete_original: true,
+            ..Config::default()
+        };
+        let paths = [animation_path.clone(), static_path.clone()];
+        let actual = convert_files(&config, Convert::Webp, paths.iter());
+        let animation = std::fs::read(directory.join("01_1.webp"));
+        let is_static_converted = directory.join("02_2.webp").exists();
+        let is_original_kept = animation_path.exists() || static_path.exists();
+        std::fs::remove_dir_all(directory).unwrap();
+
+        let actual = actual.unwrap();
+        assert_eq!(actual.len(), 2);
+        assert_eq!(decode_webp(&animation.unwrap()).1.len(), 2);
+        assert!(is_static_converted);
+        assert!(!is_original_kept);
+        assert_eq!(
+            output_files(&config, &actual, &static_path),
+            [&directory.join("02_2.webp")]
+        );
+
+        let config = Config::default();
+        assert_eq!(
+            output_files(&config, &actual, &static_path),
+            [&static_path, &directory.join("02_2.webp")]
+        );
+        let sound_path = directory.join("01_1.m4a");
+        assert_eq!(output_files(&config, &actual, &sound_path), [&sound_path]);
+    }
+
+    #[test]
+    fn test_convert_file() {
+        let directory = Path::new("Test Convert File");
+        std::fs::create_dir_all(directory).unwrap();
+        let animation_path = directory.join("01_1.png");
+        let static_path = directory.join("02_2.png");
+        let sound_path = directory.join("01_1.m4a");
+        let frames = [
+            (fill(2, 2, [255, 0, 0, 255]), 100),
+            (fill(2, 2, [0, 255, 0, 255]), 100),
+        ];
+        std::fs::write(&animation_path, apng(2, 2, &frames, 0)).unwrap();
+        RgbaImage::new(2, 2).save(&static_path).unwrap();
+        std::fs::write(&sound_path, b"sound").unwrap();
+
+        let config = Config::default();
+        let paths = [animation_path.clone(), static_path, sound_path];
+        let actual = convert_files(&config, Convert::Gif, paths.iter());
+        let gif = std::fs::read(directory.join("01_1.gif"));
+        let static_gif_exists = directory.join("02_2.gif").exists();
+        std::fs::remove_dir_all(directory).unwrap();
+
+        let actual = actual.unwrap();
+        assert_eq!(actual.len(), 1);
+        assert_eq!(actual[&animation_path], directory.join("01_1.gif"));
+        assert_eq!(decode_gif(&gif.unwrap()).1.len(), 2);
+        assert!(!static_gif_exists);
+    }
+
+    #[test]
+    fn test_converted_path() {
+        let actual = converted_path(Path::new("Pack/01_123_anim.png"), Convert::Gif);
+        assert_eq!(actual, Path::new("Pack").join("01_123_anim.gif"));
+
+        let actual = converted_path(Path::new("Pack/01_123.png"), Convert::Webp);
+        assert_eq!(actual, Path::new("Pack").join("01_123.webp"));
+    }
+}
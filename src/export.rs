@@ -0,0 +1,333 @@
+mod discord;
+mod signal;
+mod telegram;
+mod whatsapp;
+
+use image::imageops::FilterType;
+use image::RgbaImage;
+use std::path::{Path, PathBuf};
+
+use crate::convert::{merge_frames, reduce_precision};
+use crate::{Asset, Config, Export};
+
+/// Telegram, WhatsApp and Signal need emojis for every sticker, LINE has none to take them from.
+pub const PLACEHOLDER_EMOJI: &str = "🙂";
+/// Qualities tried in order until a sticker fits into a size limit, see
+/// [`reduce_precision`](crate::convert::reduce_precision).
+pub const QUALITIES: [u8; 6] = [100, 80, 60, 40, 20, 0];
+
+/// A downloaded pack as handed to the exporters.
+#[derive(Debug)]
+pub struct ExportPack<'a> {
+    pub pack_id: Option<&'a str>,
+    pub title: &'a str,
+    pub author: Option<&'a str>,
+    /// Directory the pack was downloaded into, the exports are written into subdirectories of it.
+    pub directory: &'a Path,
+    pub stickers: Vec<ExportSticker>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExportSticker {
+    pub id: String,
+    /// Position of the sticker in the pack, starting at 1.
+    pub index: usize,
+    /// The downloaded image, an APNG for animated stickers.
+    pub path: PathBuf,
+}
+
+impl ExportSticker {
+    /// Picks the image a sticker is exported from among its downloaded files, preferring
+    /// the animation over the static image.
+    pub fn from_files<'a>(
+        id: &str,
+        index: usize,
+        files: impl Iterator<Item = (Asset, &'a PathBuf)> + Clone,
+    ) -> Option<ExportSticker> {
+        [Asset::Image, Asset::Animation, Asset::Static]
+            .into_iter()
+            .find_map(|preferred| files.clone().find(|(asset, _)| *asset == preferred))
+            .map(|(_, path)| ExportSticker {
+                id: id.to_string(),
+                index,
+                path: path.clone(),
+            })
+    }
+
+    /// File name of the exported sticker, e.g. `01_123.webm`.
+    pub fn file_name(&self, ext: &str) -> String {
+        format!("{:02}_{}.{}", self.index, self.id, ext)
+    }
+}
+
+/// Exports a downloaded pack in every format of `--export`.
+pub async fn export_pack(
+    config: &Config,
+    pack: &ExportPack<'_>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    for format in &config.export {
+        let directory = pack.directory.join(format.name());
+        std::fs::create_dir_all(&directory)?;
+        match format {
+            Export::Telegram => telegram::export(config, pack, &directory).await?,
+            Export::Whatsapp => whatsapp::export(config, pack, &directory).await?,
+            Export::Signal => signal::export(config, pack, &directory)?,
+            Export::Discord => discord::export(config, pack, &directory)?,
+        }
+        println!("Exported {} to {}", pack.title, directory.display());
+    }
+
+    Ok(())
+}
+
+/// Scales an image up or down until it fits into `width` x `height`, keeping its aspect ratio.
+pub fn resize_to_fit(image: &RgbaImage, width: u32, height: u32) -> RgbaImage {
+    let scale = f64::min(
+        width as f64 / image.width() as f64,
+        height as f64 / image.height() as f64,
+    );
+    let new_width = ((image.width() as f64 * scale).round() as u32).clamp(1, width);
+    let new_height = ((image.height() as f64 * scale).round() as u32).clamp(1, height);
+    if (new_width, new_height) == image.dimensions() {
+        return image.clone();
+    }
+
+    image::imageops::resize(image, new_width, new_height, FilterType::Lanczos3)
+}
+
+/// Resizes an image to fit into a transparent `size` x `size` canvas and centers it there.
+/// Transparent pixels are made black, which compresses better than their original colors.
+pub fn fit_to_canvas(image: &RgbaImage, size: u32) -> RgbaImage {
+    let resized = resize_to_fit(image, size, size);
+    let mut canvas = RgbaImage::new(size, size);
+    image::imageops::overlay(
+        &mut canvas,
+        &resized,
+        ((size - resized.width()) / 2).into(),
+        ((size - resized.height()) / 2).into(),
+    );
+    for pixel in canvas.pixels_mut().filter(|pixel| pixel[3] == 0) {
+        pixel.0 = [0, 0, 0, 0];
+    }
+
+    canvas
+}
+
+/// Encodes an animation with `encode` at decreasing [`QUALITIES`] until it fits into
+/// `max_bytes`, then drops every second frame and starts over. Returns the first encoding
+/// that fits, or the last one tried if none does, so callers have to check its size.
+///
+/// Static images are handled as animations of a single frame.
+pub fn fit_animation(
+    mut frames: Vec<(RgbaImage, f64)>,
+    max_bytes: usize,
+    encode: impl Fn(Vec<(RgbaImage, f64)>) -> Result<Vec<u8>, Box<dyn std::error::Error>>,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    loop {
+        let mut encoded = Vec::new();
+        for quality in QUALITIES {
+            let mut reduced = frames.clone();
+            for (image, _) in &mut reduced {
+                reduce_precision(image, quality);
+            }
+            encoded = encode(merge_frames(reduced))?;
+            if encoded.len() <= max_bytes {
+                return Ok(encoded);
+            }
+        }
+
+        if frames.len() <= 1 {
+            return Ok(encoded);
+        }
+        frames = drop_every_second_frame(frames);
+    }
+}
+
+/// Halves the frame count, each remaining frame showing for the delay of the dropped one too.
+fn drop_every_second_frame(frames: Vec<(RgbaImage, f64)>) -> Vec<(RgbaImage, f64)> {
+    let mut remaining: Vec<(RgbaImage, f64)> = Vec::new();
+    for (position, (image, delay_ms)) in frames.into_iter().enumerate() {
+        match remaining.last_mut() {
+            Some((_, previous_delay_ms)) if position % 2 == 1 => *previous_delay_ms += delay_ms,
+            _ => remaining.push((image, delay_ms)),
+        }
+    }
+
+    remaining
+}
+
+/// Encodes frames of the same size as an APNG that is played `plays` times, `0` meaning
+/// forever. A single frame is written as a still PNG.
+pub fn write_apng(
+    frames: &[(RgbaImage, f64)],
+    plays: u32,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let (first, _) = frames.first().ok_or("Animation has no frames")?;
+    let mut apng = Vec::new();
+    let mut encoder = png::Encoder::new(&mut apng, first.width(), first.height());
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_compression(png::Compression::High);
+    if frames.len() > 1 {
+        encoder.set_animated(frames.len() as u32, plays)?;
+    }
+
+    let mut writer = encoder.write_header()?;
+    for (image, delay_ms) in frames {
+        if frames.len() > 1 {
+            writer.set_frame_delay(delay_ms.round().min(u16::MAX as f64) as u16, 1000)?;
+            writer.set_blend_op(png::BlendOp::Source)?;
+        }
+        writer.write_image_data(image.as_raw())?;
+    }
+    writer.finish()?;
+
+    Ok(apng)
+}
+
+pub fn encode_png(image: &RgbaImage) -> Result<Vec<u8>, image::ImageError> {
+    let mut png = std::io::Cursor::new(Vec::new());
+    image.write_to(&mut png, image::ImageFormat::Png)?;
+    Ok(png.into_inner())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// A 40x20 APNG that is played forever, whose frames are shades of red shown for
+    /// `delay_ms` each.
+    pub(crate) fn apng(frames: u32, delay_ms: u16) -> Vec<u8> {
+        let frames: Vec<(Vec<u8>, u16)> = (0..frames)
+            .map(|frame| ([frame as u8 * 10, 0, 0, 255].repeat(800), delay_ms))
+            .collect();
+        crate::convert::tests::apng(40, 20, &frames, 0)
+    }
+
+    /// Writes `stills` 40x80 PNGs followed by `animations` APNGs into `directory`, named
+    /// `01_1.png`, `02_2.png`, ..., and returns them as a pack by Author.
+    pub(crate) fn test_pack<'a>(
+        directory: &'a Path,
+        title: &'a str,
+        stills: usize,
+        animations: usize,
+    ) -> ExportPack<'a> {
+        std::fs::create_dir_all(directory).unwrap();
+        let stickers = (1..=stills + animations)
+            .map(|index| {
+                let path = directory.join(format!("{:02}_{}.png", index, index));
+                let data = if index <= stills {
+                    encode_png(&RgbaImage::new(40, 80)).unwrap()
+                } else {
+                    apng(2, 100)
+                };
+                std::fs::write(&path, data).unwrap();
+                ExportSticker {
+                    id: index.to_string(),
+                    index,
+                    path,
+                }
+            })
+            .collect();
+
+        ExportPack {
+            pack_id: Some("1"),
+            title,
+            author: Some("Author"),
+            directory,
+            stickers,
+        }
+    }
+
+    /// Returns the sorted names of the files in a directory.
+    pub(crate) fn file_names(directory: &Path) -> Vec<String> {
+        let mut files: Vec<String> = std::fs::read_dir(directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        files.sort();
+        files
+    }
+
+    #[test]
+    fn test_export_sticker_from_files() {
+        let image = PathBuf::from("01_1.png");
+        let static_image = PathBuf::from("01_1_static.png");
+        let sound = PathBuf::from("01_1.m4a");
+
+        let files = [(Asset::Sound, &sound), (Asset::Static, &static_image)];
+        let actual = ExportSticker::from_files("1", 1, files.into_iter()).unwrap();
+        assert_eq!(actual.path, static_image);
+
+        let files = [(Asset::Static, &static_image), (Asset::Image, &image)];
+        let actual = ExportSticker::from_files("1", 1, files.into_iter()).unwrap();
+        assert_eq!(actual.path, image);
+        assert_eq!(actual.file_name("webm"), "01_1.webm");
+
+        let files = [(Asset::Sound, &sound)];
+        assert!(ExportSticker::from_files("1", 1, files.into_iter()).is_none());
+    }
+
+    #[test]
+    fn test_resize_to_fit() {
+        let image = RgbaImage::new(370, 320);
+        assert_eq!(resize_to_fit(&image, 512, 512).dimensions(), (512, 443));
+        assert_eq!(resize_to_fit(&image, 96, 96).dimensions(), (96, 83));
+
+        let image = RgbaImage::new(300, 600);
+        assert_eq!(resize_to_fit(&image, 512, 512).dimensions(), (256, 512));
+
+        let image = RgbaImage::new(512, 100);
+        assert_eq!(resize_to_fit(&image, 512, 512).dimensions(), (512, 100));
+    }
+
+    #[test]
+    fn test_fit_to_canvas() {
+        let mut image = RgbaImage::from_pixel(4, 2, image::Rgba([255, 0, 0, 255]));
+        image.put_pixel(0, 0, image::Rgba([255, 255, 255, 0]));
+
+        let actual = fit_to_canvas(&image, 8);
+        assert_eq!(actual.dimensions(), (8, 8));
+        assert_eq!(actual.get_pixel(4, 0).0, [0, 0, 0, 0]);
+        assert_eq!(actual.get_pixel(4, 4).0, [255, 0, 0, 255]);
+        assert_eq!(actual.get_pixel(0, 2).0, [0, 0, 0, 0]);
+        assert_eq!(actual.get_pixel(4, 7).0, [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_fit_animation() {
+        let frames: Vec<(RgbaImage, f64)> = (0..4)
+            .map(|frame| {
+                (
+                    RgbaImage::from_pixel(2, 2, image::Rgba([frame * 64, 0, 0, 255])),
+                    10.0,
+                )
+            })
+            .collect();
+        let frame_count = |max_bytes| {
+            let encoded = fit_animation(frames.clone(), max_bytes, |frames| {
+                Ok(vec![0; frames.len() * 10])
+            })
+            .unwrap();
+            encoded.len() / 10
+        };
+        assert_eq!(frame_count(40), 4);
+        assert_eq!(frame_count(39), 2);
+        assert_eq!(frame_count(0), 1);
+
+        let apng = fit_animation(frames, usize::MAX, |frames| write_apng(&frames, 3)).unwrap();
+        assert_eq!(crate::convert::animation_plays(&apng), Some(3));
+    }
+
+    #[test]
+    fn test_drop_every_second_frame() {
+        let frames: Vec<(RgbaImage, f64)> = (0..5)
+            .map(|frame| (RgbaImage::new(frame + 1, 1), 10.0))
+            .collect();
+        let actual = drop_every_second_frame(frames);
+        let actual: Vec<(u32, f64)> = actual
+            .iter()
+            .map(|(image, delay_ms)| (image.width(), *delay_ms))
+            .collect();
+        assert_eq!(actual, [(1, 20.0), (3, 20.0), (5, 10.0)]);
+    }
+}
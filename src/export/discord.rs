@@ -0,0 +1,202 @@
+use image::RgbaImage;
+use std::path::Path;
+
+use super::{fit_animation, fit_to_canvas, write_apng, ExportPack};
+use crate::convert::{animation_plays, decode_apng, write_gif};
+use crate::{write_file_atomically, Config};
+
+const REPORT_FILE_NAME: &str = "report.txt";
+
+/// What Discord accepts for one kind of upload.
+#[derive(Debug, Clone, Copy)]
+struct Limits {
+    kind: &'static str,
+    /// Directory inside the export the files are written into.
+    directory: &'static str,
+    size: u32,
+    max_bytes: usize,
+    /// Extension of animations, Discord only animates stickers as APNG and emoji as GIF.
+    animation_ext: &'static str,
+}
+
+const STICKER: Limits = Limits {
+    kind: "sticker",
+    directory: "stickers",
+    size: 320,
+    max_bytes: 512 * 1024,
+    animation_ext: "png",
+};
+
+const EMOJI: Limits = Limits {
+    kind: "emoji",
+    directory: "emoji",
+    size: 128,
+    max_bytes: 256 * 1024,
+    animation_ext: "gif",
+};
+
+/// Writes every sticker as a 320x320 PNG or APNG Discord sticker into `stickers` and as a
+/// 128x128 PNG or GIF emoji into `emoji`, see [`fit_animation`] for how they are shrunk.
+///
+/// Files that still do not fit are not written but listed in `report.txt`.
+pub fn export(
+    config: &Config,
+    pack: &ExportPack,
+    directory: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    for limits in [STICKER, EMOJI] {
+        std::fs::create_dir_all(directory.join(limits.directory))?;
+    }
+
+    let mut report = Vec::new();
+    for sticker in &pack.stickers {
+        let data = std::fs::read(&sticker.path)?;
+        let plays = animation_plays(&data);
+        let frames = match plays {
+            Some(_) => decode_apng(&data),
+            None => image::load_from_memory(&data).map(|image| vec![(image.to_rgba8(), 0.0)]),
+        }
+        .map_err(|err| format!("Could not export sticker {}: {}", sticker.id, err))?;
+
+        for limits in [STICKER, EMOJI] {
+            let (encoded, ext) = encode(config, &limits, &frames, plays)
+                .map_err(|err| format!("Could not export sticker {}: {}", sticker.id, err))?;
+            let file_name = sticker.file_name(ext);
+            if encoded.len() > limits.max_bytes {
+                report.push(format!(
+                    "{}/{}: {} KB at the lowest quality, Discord allows {} KB for a {}",
+                    limits.directory,
+                    file_name,
+                    encoded.len().div_ceil(1024),
+                    limits.max_bytes / 1024,
+                    limits.kind
+                ));
+                continue;
+            }
+
+            let path = directory.join(limits.directory).join(&file_name);
+            write_file_atomically(&path, &encoded)?;
+            if config.verbose {
+                println!("Exported {}", path.display());
+            }
+        }
+    }
+
+    let report_path = directory.join(REPORT_FILE_NAME);
+    if report.is_empty() {
+        if report_path.exists() {
+            std::fs::remove_file(&report_path)?;
+        }
+    } else {
+        write_file_atomically(&report_path, format!("{}\n", report.join("\n")).as_bytes())?;
+        println!(
+            "{} files of {} do not fit into Discord's limits, see {}",
+            report.len(),
+            pack.title,
+            report_path.display()
+        );
+    }
+
+    Ok(())
+}
+
+/// Encodes a sticker within `limits`, returning it with its file extension. The result is
+/// larger than `limits.max_bytes` if even the smallest encoding does not fit.
+fn encode(
+    config: &Config,
+    limits: &Limits,
+    frames: &[(RgbaImage, f64)],
+    plays: Option<u32>,
+) -> Result<(Vec<u8>, &'static str), Box<dyn std::error::Error>> {
+    let frames = frames
+        .iter()
+        .map(|(image, delay_ms)| (fit_to_canvas(image, limits.size), *delay_ms))
+        .collect();
+
+    match plays {
+        Some(plays) if limits.animation_ext == "gif" => {
+            let gif = fit_animation(frames, limits.max_bytes, |frames| {
+                write_gif(frames, plays, config.gif_options)
+            })?;
+            Ok((gif, "gif"))
+        }
+        plays => {
+            let apng = fit_animation(frames, limits.max_bytes, |frames| {
+                write_apng(&frames, plays.unwrap_or(0))
+            })?;
+            Ok((apng, "png"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::tests::{apng, file_names, test_pack};
+
+    fn noise(width: u32, height: u32) -> RgbaImage {
+        RgbaImage::from_fn(width, height, |_, _| {
+            image::Rgba([fastrand::u8(..), fastrand::u8(..), fastrand::u8(..), 255])
+        })
+    }
+
+    #[test]
+    fn test_encode() {
+        let config = Config::default();
+        let frames = decode_apng(&apng(3, 100)).unwrap();
+
+        let (sticker, ext) = encode(&config, &STICKER, &frames, Some(2)).unwrap();
+        assert_eq!(ext, "png");
+        assert_eq!(animation_plays(&sticker), Some(2));
+        let frames_out = decode_apng(&sticker).unwrap();
+        assert_eq!(frames_out.len(), 3);
+        assert_eq!(frames_out[0].0.dimensions(), (320, 320));
+        assert_eq!(frames_out[0].1, 100.0);
+
+        let (emoji, ext) = encode(&config, &EMOJI, &frames, Some(2)).unwrap();
+        assert_eq!(ext, "gif");
+        let decoder = gif::DecodeOptions::new()
+            .read_info(std::io::Cursor::new(emoji))
+            .unwrap();
+        assert_eq!((decoder.width(), decoder.height()), (128, 128));
+
+        let still = [(RgbaImage::new(40, 20), 0.0)];
+        let (emoji, ext) = encode(&config, &EMOJI, &still, None).unwrap();
+        assert_eq!(ext, "png");
+        assert_eq!(animation_plays(&emoji), None);
+        let image = image::load_from_memory(&emoji).unwrap();
+        assert_eq!((image.width(), image.height()), (128, 128));
+    }
+
+    #[test]
+    fn test_encode_too_large() {
+        let limits = Limits {
+            max_bytes: 1024,
+            ..EMOJI
+        };
+        let frames = vec![(noise(128, 128), 100.0), (noise(128, 128), 100.0)];
+        let (apng, _) = encode(&Config::default(), &limits, &frames, None).unwrap();
+        assert!(apng.len() > limits.max_bytes);
+        // Every frame but the first one was dropped trying to fit it.
+        assert_eq!(animation_plays(&apng), None);
+    }
+
+    #[test]
+    fn test_export() {
+        let directory = Path::new("Test Discord");
+        let pack = test_pack(directory, "Test Discord", 1, 1);
+        let output = directory.join("discord");
+        std::fs::create_dir_all(&output).unwrap();
+        std::fs::write(output.join(REPORT_FILE_NAME), "outdated").unwrap();
+        let actual = export(&Config::default(), &pack, &output);
+        let stickers = file_names(&output.join(STICKER.directory));
+        let emoji = file_names(&output.join(EMOJI.directory));
+        let has_report = output.join(REPORT_FILE_NAME).exists();
+        std::fs::remove_dir_all(directory).unwrap();
+        assert!(actual.is_ok(), "{}", actual.unwrap_err());
+
+        assert_eq!(stickers, ["01_1.png", "02_2.png"]);
+        assert_eq!(emoji, ["01_1.png", "02_2.gif"]);
+        assert!(!has_report);
+    }
+}
@@ -0,0 +1,471 @@
+use aes::cipher::{block_padding::Pkcs7, BlockEncryptMut, KeyIvInit};
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha2::Sha256;
+use std::path::Path;
+
+use super::{
+    encode_png, fit_animation, fit_to_canvas, write_apng, ExportPack, PLACEHOLDER_EMOJI, QUALITIES,
+};
+use crate::convert::{animation_plays, decode_apng, is_converted, reduce_precision, write_webp};
+use crate::{write_file_atomically, Config};
+
+const STICKER_SIZE: u32 = 512;
+const MAX_STICKER_BYTES: usize = 300 * 1024;
+const MAX_STICKERS: usize = 200;
+const PACK_FILE_NAME: &str = "pack.json";
+/// Directory with the files as Signal's CDN serves them, `manifest.proto` and `full/<id>`.
+const UPLOAD_DIRECTORY: &str = "upload";
+const MANIFEST_FILE_NAME: &str = "manifest.proto";
+/// HKDF info Signal derives the keys of a pack from its pack key with.
+const KEY_INFO: &[u8] = b"Sticker Pack";
+
+/// Describes the export, with the key needed to share the pack once it is uploaded.
+#[derive(Debug, Serialize)]
+struct PackFile<'a> {
+    title: &'a str,
+    author: &'a str,
+    pack_key: String,
+    stickers: Vec<PackSticker>,
+}
+
+#[derive(Debug, Serialize)]
+struct PackSticker {
+    id: u32,
+    file: String,
+    emoji: &'static str,
+    content_type: &'static str,
+}
+
+/// The AES and HMAC keys everything in a pack is encrypted with.
+struct PackKeys {
+    aes_key: [u8; 32],
+    mac_key: [u8; 32],
+}
+
+impl PackKeys {
+    fn derive(pack_key: &[u8; 32]) -> PackKeys {
+        let mut keys = [0; 64];
+        hkdf::Hkdf::<Sha256>::new(Some(&[0; 32]), pack_key)
+            .expand(KEY_INFO, &mut keys)
+            .expect("64 bytes are a valid HKDF-SHA256 output length");
+        PackKeys {
+            aes_key: keys[..32].try_into().unwrap(),
+            mac_key: keys[32..].try_into().unwrap(),
+        }
+    }
+
+    /// Encrypts data like Signal encrypts attachments: the IV, the AES-256-CBC ciphertext
+    /// with PKCS#7 padding and an HMAC-SHA256 of both.
+    fn encrypt(&self, data: &[u8]) -> Result<Vec<u8>, getrandom::Error> {
+        let iv: [u8; 16] = random_bytes()?;
+        let mut encrypted = iv.to_vec();
+        encrypted.extend(
+            cbc::Encryptor::<aes::Aes256>::new(&self.aes_key.into(), &iv.into())
+                .encrypt_padded_vec_mut::<Pkcs7>(data),
+        );
+        let mut mac =
+            <Hmac<Sha256>>::new_from_slice(&self.mac_key).expect("HMAC takes keys of any length");
+        mac.update(&encrypted);
+        encrypted.extend(mac.finalize().into_bytes());
+        Ok(encrypted)
+    }
+}
+
+fn random_bytes<const N: usize>() -> Result<[u8; N], getrandom::Error> {
+    let mut bytes = [0; N];
+    getrandom::getrandom(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// Writes a Signal sticker pack: 512x512 PNG, APNG or WebP stickers of at most 300 KB,
+/// the same stickers and the pack's manifest encrypted with the pack key in `upload`,
+/// and `pack.json` with the pack key.
+///
+/// The pack key of an earlier export is kept, so a pack that was uploaded can be updated
+/// without its link changing. An earlier export with the same title, author and stickers
+/// is left as is. The first sticker is the cover of the pack.
+pub fn export(
+    config: &Config,
+    pack: &ExportPack,
+    directory: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if pack.stickers.is_empty() {
+        return Err("The pack has no stickers".into());
+    }
+    if pack.stickers.len() > MAX_STICKERS {
+        return Err(format!(
+            "{} has {} stickers, Signal packs hold at most {}",
+            pack.title,
+            pack.stickers.len(),
+            MAX_STICKERS
+        )
+        .into());
+    }
+
+    let pack_path = directory.join(PACK_FILE_NAME);
+    let author = pack.author.unwrap_or_default();
+    let previous = read_pack_file(&pack_path);
+    if !config.force
+        && previous
+            .as_ref()
+            .is_some_and(|previous| is_up_to_date(pack, author, &pack_path, previous))
+    {
+        if config.verbose {
+            println!("Skipping {} (up to date)", directory.display());
+        }
+        return Ok(());
+    }
+
+    let pack_key = match previous.as_ref().and_then(read_pack_key) {
+        Some(pack_key) => pack_key,
+        None => random_bytes()?,
+    };
+    let keys = PackKeys::derive(&pack_key);
+    let upload_directory = directory.join(UPLOAD_DIRECTORY);
+    std::fs::create_dir_all(upload_directory.join("full"))?;
+
+    let mut stickers = Vec::new();
+    for (id, sticker) in (0..).zip(&pack.stickers) {
+        let data = std::fs::read(&sticker.path)?;
+        let (encoded, ext, content_type) = encode_sticker(&data)
+            .map_err(|err| format!("Could not export sticker {}: {}", sticker.id, err))?;
+        let file_name = sticker.file_name(ext);
+        write_file_atomically(&directory.join(&file_name), &encoded)?;
+        write_file_atomically(
+            &upload_directory.join("full").join(id.to_string()),
+            &keys.encrypt(&encoded)?,
+        )?;
+
+        if config.verbose {
+            println!("Exported {}", directory.join(&file_name).display());
+        }
+        stickers.push(PackSticker {
+            id,
+            file: file_name,
+            emoji: PLACEHOLDER_EMOJI,
+            content_type,
+        });
+    }
+
+    let manifest = encode_manifest(pack.title, author, &stickers);
+    write_file_atomically(
+        &upload_directory.join(MANIFEST_FILE_NAME),
+        &keys.encrypt(&manifest)?,
+    )?;
+
+    let pack_file = PackFile {
+        title: pack.title,
+        author,
+        pack_key: pack_key
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect(),
+        stickers,
+    };
+    // Written last, so an interrupted export is not mistaken for a complete one.
+    write_file_atomically(&pack_path, &serde_json::to_vec_pretty(&pack_file)?)?;
+
+    Ok(())
+}
+
+fn read_pack_file(pack_path: &Path) -> Option<serde_json::Value> {
+    serde_json::from_slice(&std::fs::read(pack_path).ok()?).ok()
+}
+
+/// Whether an earlier export has the same title, author and number of stickers as the
+/// pack, and was written after every sticker.
+fn is_up_to_date(
+    pack: &ExportPack,
+    author: &str,
+    pack_path: &Path,
+    previous: &serde_json::Value,
+) -> bool {
+    previous["title"] == pack.title
+        && previous["author"] == author
+        && previous["stickers"].as_array().map(Vec::len) == Some(pack.stickers.len())
+        && pack
+            .stickers
+            .iter()
+            .all(|sticker| is_converted(&sticker.path, pack_path))
+}
+
+/// Reads the pack key of an earlier export from its `pack.json`.
+fn read_pack_key(pack_file: &serde_json::Value) -> Option<[u8; 32]> {
+    let hex = pack_file["pack_key"].as_str()?;
+    if hex.len() != 64 {
+        return None;
+    }
+    let mut pack_key = [0; 32];
+    for (byte, digits) in pack_key.iter_mut().zip(hex.as_bytes().chunks(2)) {
+        *byte = u8::from_str_radix(std::str::from_utf8(digits).ok()?, 16).ok()?;
+    }
+    Some(pack_key)
+}
+
+/// Encodes a sticker for Signal, returning it with its file extension and content type.
+fn encode_sticker(
+    data: &[u8],
+) -> Result<(Vec<u8>, &'static str, &'static str), Box<dyn std::error::Error>> {
+    if animation_plays(data).is_some() {
+        return Ok((encode_animated(data)?, "png", "image/png"));
+    }
+
+    let image = fit_to_canvas(&image::load_from_memory(data)?.to_rgba8(), STICKER_SIZE);
+    let png = encode_png(&image)?;
+    if png.len() <= MAX_STICKER_BYTES {
+        return Ok((png, "png", "image/png"));
+    }
+    for quality in QUALITIES {
+        let mut reduced = image.clone();
+        reduce_precision(&mut reduced, quality);
+        let webp = write_webp(&reduced)?;
+        if webp.len() <= MAX_STICKER_BYTES {
+            return Ok((webp, "webp", "image/webp"));
+        }
+    }
+
+    Err(too_large())
+}
+
+/// Encodes an APNG as a looping 512x512 APNG, see [`fit_animation`].
+fn encode_animated(data: &[u8]) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let mut frames = decode_apng(data)?;
+    for (image, _) in &mut frames {
+        *image = fit_to_canvas(image, STICKER_SIZE);
+    }
+
+    let apng = fit_animation(frames, MAX_STICKER_BYTES, |frames| write_apng(&frames, 0))?;
+    if apng.len() > MAX_STICKER_BYTES {
+        return Err(too_large());
+    }
+    Ok(apng)
+}
+
+fn too_large() -> Box<dyn std::error::Error> {
+    format!(
+        "it does not fit into the {} KB Signal allows for stickers",
+        MAX_STICKER_BYTES / 1024
+    )
+    .into()
+}
+
+/// Encodes the `Pack` message of Signal's sticker manifest:
+///
+/// ```proto
+/// message Pack {
+///   message Sticker {
+///     optional uint32 id          = 1;
+///     optional string emoji       = 2;
+///     optional string contentType = 3;
+///   }
+///   optional string  title    = 1;
+///   optional string  author   = 2;
+///   optional Sticker cover    = 3;
+///   repeated Sticker stickers = 4;
+/// }
+/// ```
+fn encode_manifest(title: &str, author: &str, stickers: &[PackSticker]) -> Vec<u8> {
+    let mut manifest = Vec::new();
+    write_bytes_field(&mut manifest, 1, title.as_bytes());
+    write_bytes_field(&mut manifest, 2, author.as_bytes());
+    if let Some(cover) = stickers.first() {
+        write_bytes_field(&mut manifest, 3, &encode_manifest_sticker(cover));
+    }
+    for sticker in stickers {
+        write_bytes_field(&mut manifest, 4, &encode_manifest_sticker(sticker));
+    }
+
+    manifest
+}
+
+fn encode_manifest_sticker(sticker: &PackSticker) -> Vec<u8> {
+    let mut message = Vec::new();
+    write_varint_field(&mut message, 1, sticker.id.into());
+    write_bytes_field(&mut message, 2, sticker.emoji.as_bytes());
+    write_bytes_field(&mut message, 3, sticker.content_type.as_bytes());
+    message
+}
+
+fn write_varint_field(buffer: &mut Vec<u8>, field: u64, value: u64) {
+    write_varint(buffer, field << 3);
+    write_varint(buffer, value);
+}
+
+/// Writes a length-delimited protobuf field, used for strings and messages.
+fn write_bytes_field(buffer: &mut Vec<u8>, field: u64, data: &[u8]) {
+    write_varint(buffer, (field << 3) | 2);
+    write_varint(buffer, data.len() as u64);
+    buffer.extend(data);
+}
+
+fn write_varint(buffer: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buffer.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buffer.push(value as u8);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::tests::{apng, test_pack};
+    use aes::cipher::BlockDecryptMut;
+    use image::AnimationDecoder;
+    use image::RgbaImage;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_write_varint() {
+        let mut buffer = Vec::new();
+        write_varint(&mut buffer, 1);
+        write_varint(&mut buffer, 300);
+        assert_eq!(buffer, [0x01, 0xac, 0x02]);
+    }
+
+    #[test]
+    fn test_encode_manifest() {
+        let stickers = [
+            PackSticker {
+                id: 0,
+                file: "01_1.png".to_string(),
+                emoji: "a",
+                content_type: "b",
+            },
+            PackSticker {
+                id: 1,
+                file: "02_2.png".to_string(),
+                emoji: "c",
+                content_type: "d",
+            },
+        ];
+        let actual = encode_manifest("Title", "", &stickers);
+        let mut expected = vec![0x0a, 5];
+        expected.extend(b"Title");
+        expected.extend([0x12, 0]);
+        expected.extend([0x1a, 8, 0x08, 0, 0x12, 1, b'a', 0x1a, 1, b'b']);
+        expected.extend([0x22, 8, 0x08, 0, 0x12, 1, b'a', 0x1a, 1, b'b']);
+        expected.extend([0x22, 8, 0x08, 1, 0x12, 1, b'c', 0x1a, 1, b'd']);
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_read_pack_key() {
+        let pack_file = serde_json::json!({ "pack_key": "0f".repeat(32) });
+        assert_eq!(read_pack_key(&pack_file), Some([0x0f; 32]));
+        let pack_file = serde_json::json!({ "pack_key": "0f" });
+        assert_eq!(read_pack_key(&pack_file), None);
+        let pack_file = serde_json::json!({ "pack_key": "zz".repeat(32) });
+        assert_eq!(read_pack_key(&pack_file), None);
+        assert_eq!(read_pack_key(&serde_json::json!({})), None);
+    }
+
+    #[test]
+    fn test_pack_keys_encrypt() {
+        let keys = PackKeys::derive(&[1; 32]);
+        assert_eq!(PackKeys::derive(&[1; 32]).aes_key, keys.aes_key);
+        assert_ne!(keys.aes_key, keys.mac_key);
+
+        let actual = keys.encrypt(b"sticker").unwrap();
+        assert_eq!(actual.len(), 16 + 16 + 32);
+        let (encrypted, mac) = actual.split_at(actual.len() - 32);
+        let mut expected_mac = <Hmac<Sha256>>::new_from_slice(&keys.mac_key).unwrap();
+        expected_mac.update(encrypted);
+        assert!(expected_mac.verify_slice(mac).is_ok());
+
+        let (iv, ciphertext) = encrypted.split_at(16);
+        let iv: [u8; 16] = iv.try_into().unwrap();
+        let decrypted = cbc::Decryptor::<aes::Aes256>::new(&keys.aes_key.into(), &iv.into())
+            .decrypt_padded_vec_mut::<Pkcs7>(ciphertext)
+            .unwrap();
+        assert_eq!(decrypted, b"sticker");
+
+        // Every file gets its own IV.
+        assert_ne!(keys.encrypt(b"sticker").unwrap(), actual);
+    }
+
+    #[test]
+    fn test_encode_sticker() {
+        let (png, ext, content_type) =
+            encode_sticker(&encode_png(&RgbaImage::new(370, 320)).unwrap()).unwrap();
+        assert_eq!((ext, content_type), ("png", "image/png"));
+        let image = image::load_from_memory(&png).unwrap();
+        assert_eq!((image.width(), image.height()), (512, 512));
+
+        let (apng, ext, _) = encode_sticker(&apng(3, 100)).unwrap();
+        assert_eq!(ext, "png");
+        assert_eq!(animation_plays(&apng), Some(0));
+        let frames = image::codecs::png::PngDecoder::new(Cursor::new(&apng))
+            .unwrap()
+            .apng()
+            .unwrap()
+            .into_frames()
+            .collect_frames()
+            .unwrap();
+        assert_eq!(frames.len(), 3);
+        assert_eq!(frames[0].buffer().dimensions(), (512, 512));
+        assert_eq!(frames[0].delay().numer_denom_ms(), (100, 1));
+    }
+
+    #[test]
+    fn test_export() {
+        let directory = Path::new("Test Signal");
+        let pack = test_pack(directory, "Test Signal", 1, 1);
+        let output = directory.join("signal");
+        std::fs::create_dir_all(&output).unwrap();
+        let actual = export(&Config::default(), &pack, &output);
+        let pack_file = std::fs::read(output.join(PACK_FILE_NAME));
+        let sticker = std::fs::read(output.join("02_2.png"));
+        let upload = output.join(UPLOAD_DIRECTORY);
+        let manifest = std::fs::read(upload.join(MANIFEST_FILE_NAME));
+        let blob = std::fs::read(upload.join("full").join("1"));
+
+        // Exporting again skips the pack, and forcing it keeps the pack key.
+        let skipped = export(&Config::default(), &pack, &output);
+        let skipped_manifest = std::fs::read(upload.join(MANIFEST_FILE_NAME));
+        let config = Config {
+            force: true,
+            ..Config::default()
+        };
+        let forced = export(&config, &pack, &output);
+        let forced_manifest = std::fs::read(upload.join(MANIFEST_FILE_NAME));
+        let forced_pack_file = std::fs::read(output.join(PACK_FILE_NAME));
+
+        // A new title is exported without forcing it.
+        let renamed_pack = ExportPack {
+            title: "Renamed Signal",
+            ..pack
+        };
+        let renamed = export(&Config::default(), &renamed_pack, &output);
+        let renamed_pack_file = std::fs::read(output.join(PACK_FILE_NAME));
+        std::fs::remove_dir_all(directory).unwrap();
+        assert!(actual.is_ok(), "{}", actual.unwrap_err());
+        assert!(skipped.is_ok(), "{}", skipped.unwrap_err());
+        assert!(forced.is_ok(), "{}", forced.unwrap_err());
+        let manifest = manifest.unwrap();
+        assert_eq!(skipped_manifest.unwrap(), manifest);
+        assert_ne!(forced_manifest.unwrap(), manifest);
+
+        let pack_file: serde_json::Value = serde_json::from_slice(&pack_file.unwrap()).unwrap();
+        assert_eq!(pack_file["title"], "Test Signal");
+        assert_eq!(pack_file["author"], "Author");
+        assert_eq!(pack_file["pack_key"].as_str().unwrap().len(), 64);
+        assert_eq!(pack_file["stickers"][1]["id"], 1);
+        assert_eq!(pack_file["stickers"][1]["file"], "02_2.png");
+        assert_eq!(pack_file["stickers"][1]["emoji"], PLACEHOLDER_EMOJI);
+        let forced_pack_file: serde_json::Value =
+            serde_json::from_slice(&forced_pack_file.unwrap()).unwrap();
+        assert_eq!(forced_pack_file["pack_key"], pack_file["pack_key"]);
+        assert!(renamed.is_ok(), "{}", renamed.unwrap_err());
+        let renamed_pack_file: serde_json::Value =
+            serde_json::from_slice(&renamed_pack_file.unwrap()).unwrap();
+        assert_eq!(renamed_pack_file["title"], "Renamed Signal");
+        assert_eq!(renamed_pack_file["pack_key"], pack_file["pack_key"]);
+
+        // Blobs are the padded sticker between IV and MAC.
+        let sticker = sticker.unwrap();
+        assert_eq!(blob.unwrap().len(), 16 + (sticker.len() / 16 + 1) * 16 + 32);
+        assert!(manifest.len() > 48);
+    }
+}
@@ -0,0 +1,288 @@
+use image::codecs::png::PngDecoder;
+use image::AnimationDecoder;
+use serde::Serialize;
+use std::io::Cursor;
+use std::path::Path;
+use tokio::process::Command;
+
+use super::{encode_png, resize_to_fit, ExportPack, ExportSticker, PLACEHOLDER_EMOJI};
+use crate::convert::animation_plays;
+use crate::{write_file_atomically, Config};
+
+/// Length of the long side of a sticker, the short side may be shorter.
+const STICKER_SIZE: u32 = 512;
+const MAX_STATIC_BYTES: usize = 512 * 1024;
+const MAX_VIDEO_BYTES: usize = 256 * 1024;
+const MAX_VIDEO_DURATION_MS: f64 = 3000.0;
+const MAX_VIDEO_FPS: u32 = 30;
+/// VP9 quality levels tried in order until a video fits into `MAX_VIDEO_BYTES`.
+const VIDEO_CRFS: [u32; 5] = [24, 32, 40, 50, 63];
+const DESCRIPTOR_FILE_NAME: &str = "stickers.json";
+
+/// The parameters of the Bot API's `createNewStickerSet`, with file names instead of uploads.
+#[derive(Debug, Serialize)]
+struct StickerSet<'a> {
+    title: &'a str,
+    sticker_type: &'static str,
+    stickers: Vec<InputSticker>,
+}
+
+#[derive(Debug, Serialize)]
+struct InputSticker {
+    sticker: String,
+    format: &'static str,
+    emoji_list: Vec<String>,
+}
+
+/// Writes a Telegram sticker set: static stickers as PNG, or lossless WebP if the PNG is
+/// too large, animated stickers as VP9 WebM encoded by ffmpeg, and `stickers.json`.
+pub async fn export(
+    config: &Config,
+    pack: &ExportPack<'_>,
+    directory: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut stickers = Vec::new();
+    for sticker in &pack.stickers {
+        let data = std::fs::read(&sticker.path)?;
+        let (file_name, format) = if animation_plays(&data).is_some() {
+            let file_name = sticker.file_name("webm");
+            export_video(config, sticker, &data, &directory.join(&file_name)).await?;
+            (file_name, "video")
+        } else {
+            let (sticker_data, ext) = export_static(&data)
+                .map_err(|err| format!("Could not export sticker {}: {}", sticker.id, err))?;
+            let file_name = sticker.file_name(ext);
+            write_file_atomically(&directory.join(&file_name), &sticker_data)?;
+            (file_name, "static")
+        };
+
+        if config.verbose {
+            println!("Exported {}", directory.join(&file_name).display());
+        }
+        stickers.push(InputSticker {
+            sticker: file_name,
+            format,
+            emoji_list: vec![PLACEHOLDER_EMOJI.to_string()],
+        });
+    }
+
+    let descriptor = StickerSet {
+        title: pack.title,
+        sticker_type: "regular",
+        stickers,
+    };
+    write_file_atomically(
+        &directory.join(DESCRIPTOR_FILE_NAME),
+        &serde_json::to_vec_pretty(&descriptor)?,
+    )?;
+
+    Ok(())
+}
+
+/// Resizes a static sticker to `STICKER_SIZE` and returns it with its file extension.
+fn export_static(data: &[u8]) -> Result<(Vec<u8>, &'static str), Box<dyn std::error::Error>> {
+    let image = image::load_from_memory(data)?.to_rgba8();
+    let image = resize_to_fit(&image, STICKER_SIZE, STICKER_SIZE);
+
+    let png = encode_png(&image)?;
+    if png.len() <= MAX_STATIC_BYTES {
+        return Ok((png, "png"));
+    }
+
+    let mut webp = Vec::new();
+    image_webp::WebPEncoder::new(&mut webp).encode(
+        image.as_raw(),
+        image.width(),
+        image.height(),
+        image_webp::ColorType::Rgba8,
+    )?;
+    if webp.len() <= MAX_STATIC_BYTES {
+        return Ok((webp, "webp"));
+    }
+
+    Err(format!(
+        "{} KB is larger than the {} KB Telegram allows",
+        webp.len() / 1024,
+        MAX_STATIC_BYTES / 1024
+    )
+    .into())
+}
+
+/// Encodes an APNG as a WebM of at most 3 seconds, speeding up longer animations, with
+/// decreasing quality until it fits into Telegram's size limit.
+async fn export_video(
+    config: &Config,
+    sticker: &ExportSticker,
+    data: &[u8],
+    output_path: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let duration_ms = animation_duration(data).map_err(|err| {
+        format!(
+            "Could not read animation of sticker {}: {}",
+            sticker.id, err
+        )
+    })?;
+
+    for crf in VIDEO_CRFS {
+        let output = Command::new(&config.ffmpeg)
+            .args(["-y", "-loglevel", "error", "-f", "apng", "-i"])
+            .arg(&sticker.path)
+            .args(["-an", "-vf", &video_filter(duration_ms)])
+            .args(["-c:v", "libvpx-vp9", "-pix_fmt", "yuva420p", "-b:v", "0"])
+            .args(["-crf", &crf.to_string(), "-f", "webm"])
+            .arg(output_path)
+            .output()
+            .await
+            .map_err(|err| {
+                format!(
+                    "Exporting animated stickers to Telegram needs ffmpeg with libvpx-vp9, could not run {}: {}",
+                    config.ffmpeg.display(),
+                    err
+                )
+            })?;
+        if !output.status.success() {
+            return Err(format!(
+                "ffmpeg could not encode sticker {}: {}",
+                sticker.id,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )
+            .into());
+        }
+
+        if std::fs::metadata(output_path)?.len() as usize <= MAX_VIDEO_BYTES {
+            return Ok(());
+        }
+    }
+
+    std::fs::remove_file(output_path)?;
+    Err(format!(
+        "Sticker {} does not fit into the {} KB Telegram allows for videos",
+        sticker.id,
+        MAX_VIDEO_BYTES / 1024
+    )
+    .into())
+}
+
+/// Scales the animation to `STICKER_SIZE` and at most 30 frames per second, and speeds it
+/// up to 3 seconds if it is longer.
+fn video_filter(duration_ms: f64) -> String {
+    let mut filter = String::new();
+    if duration_ms > MAX_VIDEO_DURATION_MS {
+        // Rounded down, rounding up would leave the video a little longer than allowed.
+        let factor = (MAX_VIDEO_DURATION_MS / duration_ms * 10000.0).floor() / 10000.0;
+        filter.push_str(&format!("setpts=PTS*{:.4},", factor));
+    }
+    filter.push_str(&format!(
+        "scale={size}:{size}:force_original_aspect_ratio=decrease:flags=lanczos,fps={fps}",
+        size = STICKER_SIZE,
+        fps = MAX_VIDEO_FPS
+    ));
+
+    filter
+}
+
+/// Returns the length of a single play of an APNG in milliseconds.
+fn animation_duration(data: &[u8]) -> Result<f64, image::ImageError> {
+    let mut duration_ms = 0.0;
+    for frame in PngDecoder::new(Cursor::new(data))?.apng()?.into_frames() {
+        let (numerator, denominator) = frame?.delay().numer_denom_ms();
+        duration_ms += numerator as f64 / denominator as f64;
+    }
+
+    Ok(duration_ms)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::tests::{apng, file_names, test_pack};
+    use image::RgbaImage;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_export_static() {
+        let png = encode_png(&RgbaImage::new(370, 320)).unwrap();
+        let (data, ext) = export_static(&png).unwrap();
+        assert_eq!(ext, "png");
+        let image = image::load_from_memory(&data).unwrap();
+        assert_eq!((image.width(), image.height()), (512, 443));
+
+        // Noise does not compress into 512 KB.
+        let noise = RgbaImage::from_fn(512, 512, |_, _| {
+            image::Rgba([fastrand::u8(..), fastrand::u8(..), fastrand::u8(..), 255])
+        });
+        assert!(export_static(&encode_png(&noise).unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_animation_duration() {
+        assert_eq!(animation_duration(&apng(3, 100)).unwrap(), 300.0);
+        assert!(animation_duration(b"not a png").is_err());
+    }
+
+    #[test]
+    fn test_video_filter() {
+        assert_eq!(
+            video_filter(2000.0),
+            "scale=512:512:force_original_aspect_ratio=decrease:flags=lanczos,fps=30"
+        );
+        assert_eq!(
+            video_filter(4000.0),
+            "setpts=PTS*0.7500,scale=512:512:force_original_aspect_ratio=decrease:flags=lanczos,fps=30"
+        );
+        // 3000 / 3001 is 0.99967, which must not be rounded up to 0.9997.
+        assert!(video_filter(3001.0).starts_with("setpts=PTS*0.9996,"));
+        assert!(video_filter(3000.5).starts_with("setpts=PTS*0.9998,"));
+    }
+
+    async fn export_test_pack(
+        config: &Config,
+        name: &str,
+    ) -> (Result<(), String>, Vec<String>, String) {
+        let directory = Path::new(name);
+        let pack = test_pack(directory, "Test Telegram", 1, 1);
+        let output = directory.join("telegram");
+        std::fs::create_dir_all(&output).unwrap();
+        let actual = export(config, &pack, &output)
+            .await
+            .map_err(|err| err.to_string());
+        let files = file_names(&output);
+        let descriptor =
+            std::fs::read_to_string(output.join(DESCRIPTOR_FILE_NAME)).unwrap_or_default();
+        std::fs::remove_dir_all(directory).unwrap();
+
+        (actual, files, descriptor)
+    }
+
+    #[tokio::test]
+    async fn test_export_without_ffmpeg() {
+        let config = Config {
+            ffmpeg: PathBuf::from("missing-ffmpeg-binary"),
+            ..Config::default()
+        };
+        let (actual, files, _) = export_test_pack(&config, "Test Telegram Without FFmpeg").await;
+        assert!(actual.unwrap_err().contains("needs ffmpeg"));
+        assert_eq!(files, ["01_1.png"]);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs ffmpeg"]
+    async fn test_export() {
+        let (actual, files, descriptor) =
+            export_test_pack(&Config::default(), "Test Telegram").await;
+        assert!(actual.is_ok(), "{}", actual.unwrap_err());
+        assert_eq!(files, ["01_1.png", "02_2.webm", DESCRIPTOR_FILE_NAME]);
+
+        let descriptor: serde_json::Value = serde_json::from_str(&descriptor).unwrap();
+        assert_eq!(descriptor["title"], "Test Telegram");
+        assert_eq!(descriptor["sticker_type"], "regular");
+        assert_eq!(descriptor["stickers"][0]["sticker"], "01_1.png");
+        assert_eq!(descriptor["stickers"][0]["format"], "static");
+        assert_eq!(
+            descriptor["stickers"][0]["emoji_list"][0],
+            PLACEHOLDER_EMOJI
+        );
+        assert_eq!(descriptor["stickers"][1]["sticker"], "02_2.webm");
+        assert_eq!(descriptor["stickers"][1]["format"], "video");
+    }
+}
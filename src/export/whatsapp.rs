@@ -0,0 +1,447 @@
+use serde::Serialize;
+use std::io::{Cursor, Write};
+use std::path::Path;
+
+use super::{
+    encode_png, fit_animation, fit_to_canvas, ExportPack, ExportSticker, PLACEHOLDER_EMOJI,
+    QUALITIES,
+};
+use crate::convert::{
+    animation_plays, decode_apng, reduce_precision, write_animated_webp, write_webp,
+};
+use crate::{ensure_success, fetch, write_file_atomically, Config};
+
+const STICKER_SIZE: u32 = 512;
+const TRAY_ICON_SIZE: u32 = 96;
+const MAX_STATIC_BYTES: usize = 100 * 1024;
+const MAX_ANIMATED_BYTES: usize = 500 * 1024;
+const MAX_TRAY_ICON_BYTES: usize = 50 * 1024;
+const MIN_STICKERS: usize = 3;
+const MAX_STICKERS: usize = 30;
+const MAX_ANIMATION_DURATION_MS: f64 = 10_000.0;
+const MIN_FRAME_DURATION_MS: f64 = 8.0;
+const CONTENTS_FILE_NAME: &str = "contents.json";
+const TRAY_ICON_FILE_NAME: &str = "tray.png";
+
+/// `contents.json` as read by WhatsApp's sticker sample apps.
+#[derive(Debug, Serialize)]
+struct Contents {
+    android_play_store_link: String,
+    ios_app_store_link: String,
+    sticker_packs: Vec<StickerPack>,
+}
+
+#[derive(Debug, Serialize)]
+struct StickerPack {
+    identifier: String,
+    name: String,
+    publisher: String,
+    tray_image_file: String,
+    image_data_version: String,
+    avoid_cache: bool,
+    publisher_email: String,
+    publisher_website: String,
+    privacy_policy_website: String,
+    license_agreement_website: String,
+    animated_sticker_pack: bool,
+    stickers: Vec<Sticker>,
+}
+
+#[derive(Debug, Serialize)]
+struct Sticker {
+    image_file: String,
+    emojis: Vec<String>,
+}
+
+/// Writes WhatsApp sticker packs: a directory per pack with 512x512 WebP stickers and a
+/// 96x96 tray icon, `contents.json` describing all of them, and a `.wastickers` bundle per pack.
+///
+/// WhatsApp packs are either static or animated and hold 3 to 30 stickers, so LINE packs
+/// are split up as needed. Stickers that cannot fill a pack of their kind are left out.
+pub async fn export(
+    config: &Config,
+    pack: &ExportPack<'_>,
+    directory: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut static_stickers = Vec::new();
+    let mut animated_stickers = Vec::new();
+    for sticker in &pack.stickers {
+        let data = std::fs::read(&sticker.path)?;
+        let result = if animation_plays(&data).is_some() {
+            encode_animated(&data).map(|webp| (webp, true))
+        } else {
+            encode_static(&data).map(|webp| (webp, false))
+        };
+        let (webp, is_animated) =
+            result.map_err(|err| format!("Could not export sticker {}: {}", sticker.id, err))?;
+        if is_animated {
+            animated_stickers.push((sticker, webp));
+        } else {
+            static_stickers.push((sticker, webp));
+        }
+    }
+
+    let main_image = match pack.pack_id {
+        Some(pack_id) => fetch_main_image(config, pack_id).await?,
+        None => None,
+    };
+    let tray_icon = match main_image {
+        Some(data) => encode_tray_icon(&data)?,
+        None => {
+            let first = pack.stickers.first().ok_or("The pack has no stickers")?;
+            encode_tray_icon(&std::fs::read(&first.path)?)?
+        }
+    };
+
+    let groups = [(static_stickers, false), (animated_stickers, true)];
+    let chunk_count: usize = groups
+        .iter()
+        .map(|(stickers, _)| split(stickers).len())
+        .sum();
+    let mut sticker_packs = Vec::new();
+    for (stickers, is_animated) in &groups {
+        if !stickers.is_empty() && stickers.len() < MIN_STICKERS {
+            println!(
+                "Skipping {} {} stickers of {}, WhatsApp packs need at least {}",
+                stickers.len(),
+                if *is_animated { "animated" } else { "static" },
+                pack.title,
+                MIN_STICKERS
+            );
+        }
+
+        for chunk in split(stickers) {
+            let (identifier, name) = pack_name(pack, sticker_packs.len() + 1, chunk_count);
+
+            let sticker_pack = StickerPack {
+                identifier,
+                name,
+                publisher: pack.author.unwrap_or_default().to_string(),
+                tray_image_file: TRAY_ICON_FILE_NAME.to_string(),
+                image_data_version: "1".to_string(),
+                avoid_cache: false,
+                publisher_email: String::new(),
+                publisher_website: String::new(),
+                privacy_policy_website: String::new(),
+                license_agreement_website: String::new(),
+                animated_sticker_pack: *is_animated,
+                stickers: chunk
+                    .iter()
+                    .map(|(sticker, _)| Sticker {
+                        image_file: sticker.file_name("webp"),
+                        emojis: vec![PLACEHOLDER_EMOJI.to_string()],
+                    })
+                    .collect(),
+            };
+            write_pack(config, directory, &sticker_pack, chunk, &tray_icon)?;
+            sticker_packs.push(sticker_pack);
+        }
+    }
+
+    let contents = Contents {
+        android_play_store_link: String::new(),
+        ios_app_store_link: String::new(),
+        sticker_packs,
+    };
+    write_file_atomically(
+        &directory.join(CONTENTS_FILE_NAME),
+        &serde_json::to_vec_pretty(&contents)?,
+    )?;
+
+    Ok(())
+}
+
+/// Returns the identifier and name of the `number`th of `count` WhatsApp packs of a LINE pack.
+fn pack_name(pack: &ExportPack, number: usize, count: usize) -> (String, String) {
+    let identifier = pack.pack_id.unwrap_or("pack");
+    if count > 1 {
+        (
+            format!("{}_{}", identifier, number),
+            format!("{} ({}/{})", pack.title, number, count),
+        )
+    } else {
+        (identifier.to_string(), pack.title.to_string())
+    }
+}
+
+/// Writes the files of a pack into a directory named after its identifier, and the same
+/// files plus `title.txt` and `author.txt` into `<identifier>.wastickers`.
+fn write_pack(
+    config: &Config,
+    directory: &Path,
+    sticker_pack: &StickerPack,
+    stickers: &[(&ExportSticker, Vec<u8>)],
+    tray_icon: &[u8],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut files = vec![(TRAY_ICON_FILE_NAME.to_string(), tray_icon)];
+    for (sticker, webp) in stickers {
+        files.push((sticker.file_name("webp"), webp));
+    }
+
+    let pack_directory = directory.join(&sticker_pack.identifier);
+    std::fs::create_dir_all(&pack_directory)?;
+    for (file_name, data) in &files {
+        write_file_atomically(&pack_directory.join(file_name), data)?;
+    }
+
+    let mut bundle = zip::ZipWriter::new(Cursor::new(Vec::new()));
+    let options =
+        zip::write::SimpleFileOptions::default().compression_method(zip::CompressionMethod::Stored);
+    bundle.start_file("title.txt", options)?;
+    bundle.write_all(sticker_pack.name.as_bytes())?;
+    bundle.start_file("author.txt", options)?;
+    bundle.write_all(sticker_pack.publisher.as_bytes())?;
+    for (file_name, data) in &files {
+        bundle.start_file(file_name.as_str(), options)?;
+        bundle.write_all(data)?;
+    }
+    let bundle_path = directory.join(format!("{}.wastickers", sticker_pack.identifier));
+    write_file_atomically(&bundle_path, bundle.finish()?.get_ref())?;
+
+    if config.verbose {
+        println!("Exported {}", bundle_path.display());
+    }
+    Ok(())
+}
+
+/// Splits stickers into as few packs of `MIN_STICKERS` to `MAX_STICKERS` stickers as
+/// possible, of nearly equal size. Returns no packs for fewer than `MIN_STICKERS` stickers.
+fn split<T>(stickers: &[T]) -> Vec<&[T]> {
+    if stickers.len() < MIN_STICKERS {
+        return Vec::new();
+    }
+
+    let pack_count = stickers.len().div_ceil(MAX_STICKERS);
+    let mut packs = Vec::new();
+    let mut rest = stickers;
+    for remaining_packs in (1..=pack_count).rev() {
+        let (pack, next) = rest.split_at(rest.len().div_ceil(remaining_packs));
+        packs.push(pack);
+        rest = next;
+    }
+
+    packs
+}
+
+fn encode_static(data: &[u8]) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let image = fit_to_canvas(&image::load_from_memory(data)?.to_rgba8(), STICKER_SIZE);
+    for quality in QUALITIES {
+        let mut reduced = image.clone();
+        reduce_precision(&mut reduced, quality);
+        let webp = write_webp(&reduced)?;
+        if webp.len() <= MAX_STATIC_BYTES {
+            return Ok(webp);
+        }
+    }
+
+    Err(format!(
+        "it does not fit into the {} KB WhatsApp allows for static stickers",
+        MAX_STATIC_BYTES / 1024
+    )
+    .into())
+}
+
+/// Encodes an APNG as a looping WebP of at most 10 seconds, speeding up longer animations,
+/// see [`fit_animation`].
+fn encode_animated(data: &[u8]) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let mut frames = decode_apng(data)?;
+    let duration_ms: f64 = frames.iter().map(|(_, delay_ms)| delay_ms).sum();
+    let speed = f64::max(duration_ms / MAX_ANIMATION_DURATION_MS, 1.0);
+    for (image, delay_ms) in &mut frames {
+        *image = fit_to_canvas(image, STICKER_SIZE);
+        *delay_ms = f64::max(*delay_ms / speed, MIN_FRAME_DURATION_MS);
+    }
+
+    let webp = fit_animation(frames, MAX_ANIMATED_BYTES, |frames| {
+        write_animated_webp(&frames, 0)
+    })?;
+    if webp.len() > MAX_ANIMATED_BYTES {
+        return Err(format!(
+            "it does not fit into the {} KB WhatsApp allows for animated stickers",
+            MAX_ANIMATED_BYTES / 1024
+        )
+        .into());
+    }
+    Ok(webp)
+}
+
+fn encode_tray_icon(data: &[u8]) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let image = fit_to_canvas(&image::load_from_memory(data)?.to_rgba8(), TRAY_ICON_SIZE);
+    let png = encode_png(&image)?;
+    if png.len() > MAX_TRAY_ICON_BYTES {
+        return Err(format!(
+            "The tray icon does not fit into the {} KB WhatsApp allows",
+            MAX_TRAY_ICON_BYTES / 1024
+        )
+        .into());
+    }
+
+    Ok(png)
+}
+
+pub fn main_image_url(cdn_url: &str, pack_id: &str) -> String {
+    format!(
+        "{}/stickershop/v1/product/{}/LINEStorePC/main.png",
+        cdn_url, pack_id
+    )
+}
+
+/// Fetches the image the store shows for a pack. Returns `Ok(None)` if the CDN has none.
+async fn fetch_main_image(
+    config: &Config,
+    pack_id: &str,
+) -> Result<Option<Vec<u8>>, Box<dyn std::error::Error>> {
+    let url = main_image_url(&config.cdn_url, pack_id);
+    let response = fetch(config, &url).await?;
+    if matches!(response.status.as_u16(), 403 | 404) {
+        return Ok(None);
+    }
+    ensure_success(&url, &response)?;
+
+    Ok(Some(response.body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::tests::{apng, test_pack};
+    use image::RgbaImage;
+    use std::io::Read;
+
+    fn decode_webp(data: &[u8]) -> image_webp::WebPDecoder<Cursor<&[u8]>> {
+        image_webp::WebPDecoder::new(Cursor::new(data)).unwrap()
+    }
+
+    #[test]
+    fn test_split() {
+        let stickers: Vec<usize> = (0..70).collect();
+        let lengths = |count: usize| -> Vec<usize> {
+            split(&stickers[..count])
+                .iter()
+                .map(|pack| pack.len())
+                .collect()
+        };
+        assert!(lengths(2).is_empty());
+        assert_eq!(lengths(3), [3]);
+        assert_eq!(lengths(30), [30]);
+        assert_eq!(lengths(31), [16, 15]);
+        assert_eq!(lengths(40), [20, 20]);
+        assert_eq!(lengths(70), [24, 23, 23]);
+        assert_eq!(split(&stickers[..31])[1][0], 16);
+    }
+
+    #[test]
+    fn test_encode_static() {
+        let webp = encode_static(&encode_png(&RgbaImage::new(370, 320)).unwrap()).unwrap();
+        assert!(webp.len() <= MAX_STATIC_BYTES);
+        let decoder = decode_webp(&webp);
+        assert_eq!(decoder.dimensions(), (512, 512));
+        assert!(!decoder.is_animated());
+    }
+
+    #[test]
+    fn test_encode_animated() {
+        let webp = encode_animated(&apng(3, 100)).unwrap();
+        let mut decoder = decode_webp(&webp);
+        assert_eq!(decoder.dimensions(), (512, 512));
+        assert_eq!(decoder.num_frames(), 3);
+        assert_eq!(decoder.loop_count(), image_webp::LoopCount::Forever);
+        let mut buffer = vec![0; decoder.output_buffer_size().unwrap()];
+        assert_eq!(decoder.read_frame(&mut buffer).unwrap(), 100);
+
+        // Longer animations are sped up to 10 seconds.
+        let webp = encode_animated(&apng(4, 5000)).unwrap();
+        let mut decoder = decode_webp(&webp);
+        let mut buffer = vec![0; decoder.output_buffer_size().unwrap()];
+        assert_eq!(decoder.read_frame(&mut buffer).unwrap(), 2500);
+    }
+
+    #[test]
+    fn test_encode_tray_icon() {
+        let png = encode_tray_icon(&encode_png(&RgbaImage::new(240, 200)).unwrap()).unwrap();
+        let image = image::load_from_memory(&png).unwrap();
+        assert_eq!((image.width(), image.height()), (96, 96));
+    }
+
+    #[test]
+    fn test_main_image_url() {
+        assert_eq!(
+            main_image_url("https://stickershop.line-scdn.net", "6920150"),
+            "https://stickershop.line-scdn.net/stickershop/v1/product/6920150/LINEStorePC/main.png"
+        );
+    }
+
+    #[test]
+    fn test_pack_name() {
+        let mut pack = ExportPack {
+            pack_id: Some("1"),
+            title: "Title",
+            author: None,
+            directory: Path::new("."),
+            stickers: Vec::new(),
+        };
+        assert_eq!(pack_name(&pack, 1, 1), ("1".into(), "Title".into()));
+        assert_eq!(pack_name(&pack, 2, 3), ("1_2".into(), "Title (2/3)".into()));
+
+        pack.pack_id = None;
+        assert_eq!(pack_name(&pack, 1, 1), ("pack".into(), "Title".into()));
+    }
+
+    #[tokio::test]
+    async fn test_export() {
+        let mut server = mockito::Server::new_async().await;
+        let _m = server
+            .mock("GET", "/stickershop/v1/product/1/LINEStorePC/main.png")
+            .with_status(200)
+            .with_body(encode_png(&RgbaImage::new(240, 240)).unwrap())
+            .create_async()
+            .await;
+
+        let directory = Path::new("Test WhatsApp");
+        let pack = test_pack(directory, "Test WhatsApp", 3, 2);
+        let config = Config {
+            cdn_url: server.url(),
+            ..Config::default()
+        };
+        let output = directory.join("whatsapp");
+        std::fs::create_dir_all(&output).unwrap();
+        let actual = export(&config, &pack, &output).await;
+        let contents = std::fs::read(output.join(CONTENTS_FILE_NAME));
+        let bundle = std::fs::read(output.join("1.wastickers"));
+        let tray_icon = std::fs::read(output.join("1").join(TRAY_ICON_FILE_NAME));
+        let sticker = std::fs::read(output.join("1").join("01_1.webp"));
+        std::fs::remove_dir_all(directory).unwrap();
+        assert!(actual.is_ok(), "{}", actual.unwrap_err());
+
+        let tray_icon = image::load_from_memory(&tray_icon.unwrap()).unwrap();
+        assert_eq!((tray_icon.width(), tray_icon.height()), (96, 96));
+        assert_eq!(decode_webp(&sticker.unwrap()).dimensions(), (512, 512));
+
+        // The 2 animated stickers cannot fill a pack of their own.
+        let contents: serde_json::Value = serde_json::from_slice(&contents.unwrap()).unwrap();
+        let packs = contents["sticker_packs"].as_array().unwrap();
+        assert_eq!(packs.len(), 1);
+        assert_eq!(packs[0]["identifier"], "1");
+        assert_eq!(packs[0]["name"], "Test WhatsApp");
+        assert_eq!(packs[0]["publisher"], "Author");
+        assert_eq!(packs[0]["tray_image_file"], TRAY_ICON_FILE_NAME);
+        assert_eq!(packs[0]["animated_sticker_pack"], false);
+        let pack_stickers = packs[0]["stickers"].as_array().unwrap();
+        assert_eq!(pack_stickers.len(), 3);
+        assert_eq!(pack_stickers[0]["image_file"], "01_1.webp");
+        assert_eq!(pack_stickers[0]["emojis"][0], PLACEHOLDER_EMOJI);
+
+        let mut bundle = zip::ZipArchive::new(Cursor::new(bundle.unwrap())).unwrap();
+        assert_eq!(bundle.len(), 6);
+        let mut author = String::new();
+        bundle
+            .by_name("author.txt")
+            .unwrap()
+            .read_to_string(&mut author)
+            .unwrap();
+        assert_eq!(author, "Author");
+        assert!(bundle.by_name("title.txt").is_ok());
+        assert!(bundle.by_name(TRAY_ICON_FILE_NAME).is_ok());
+        assert!(bundle.by_name("03_3.webp").is_ok());
+    }
+}
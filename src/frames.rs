@@ -0,0 +1,289 @@
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+use crate::convert::{decode_apng, is_converted};
+use crate::export::encode_png;
+use crate::{write_file_atomically, Config};
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+const FRAMES_FILE_NAME: &str = "frames.json";
+
+/// `frames.json`, the timing and layout of an APNG as stored in its `acTL` and `fcTL` chunks.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Animation {
+    /// Size of the canvas every frame is drawn onto.
+    pub width: u32,
+    pub height: u32,
+    /// How often the animation is played, `0` meaning forever.
+    pub plays: u32,
+    pub frames: Vec<Frame>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Frame {
+    /// The frame as shown, composed onto the canvas, relative to `frames.json`.
+    pub file_name: String,
+    pub delay_ms: f64,
+    /// The delay as stored, in seconds as a fraction.
+    pub delay_num: u16,
+    pub delay_den: u16,
+    /// The region of the canvas the frame draws.
+    pub x_offset: u32,
+    pub y_offset: u32,
+    pub width: u32,
+    pub height: u32,
+    /// `none`, `background` or `previous`.
+    pub dispose_op: &'static str,
+    /// `source` or `over`.
+    pub blend_op: &'static str,
+}
+
+/// Returns the directory the frames of an APNG are written into, e.g. `01_123_frames` for
+/// `01_123.png`.
+pub fn frames_directory(path: &Path) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!("{}_frames", stem))
+}
+
+/// Writes the frames of every APNG among `paths` into a directory next to it.
+pub fn explode_files<'a>(
+    config: &Config,
+    paths: impl Iterator<Item = &'a PathBuf>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    for path in paths {
+        explode_file(config, path)?;
+    }
+
+    Ok(())
+}
+
+/// Writes every frame of an APNG as a PNG of the whole canvas, plus `frames.json`. Returns
+/// the directory written into, or `Ok(None)` if the file is not an animated PNG.
+pub fn explode_file(
+    config: &Config,
+    path: &Path,
+) -> Result<Option<PathBuf>, Box<dyn std::error::Error>> {
+    if path.extension().and_then(|ext| ext.to_str()) != Some("png") {
+        return Ok(None);
+    }
+    let data = std::fs::read(path)?;
+    let Some(animation) = read_animation(&data)
+        .map_err(|err| format!("Could not read {}: {}", path.display(), err))?
+    else {
+        return Ok(None);
+    };
+
+    let directory = frames_directory(path);
+    let frames_file = directory.join(FRAMES_FILE_NAME);
+    if !config.force && is_converted(path, &frames_file) {
+        if config.verbose {
+            println!("Skipping {} (up to date)", directory.display());
+        }
+        return Ok(Some(directory));
+    }
+
+    let images = decode_apng(&data)?;
+    if images.len() != animation.frames.len() {
+        return Err(format!(
+            "{} has {} frame control chunks but {} frames",
+            path.display(),
+            animation.frames.len(),
+            images.len()
+        )
+        .into());
+    }
+
+    std::fs::create_dir_all(&directory)?;
+    for (frame, (image, _)) in animation.frames.iter().zip(&images) {
+        write_file_atomically(&directory.join(&frame.file_name), &encode_png(image)?)?;
+    }
+    // Written last, so an interrupted run is not mistaken for a complete one.
+    write_file_atomically(&frames_file, &serde_json::to_vec_pretty(&animation)?)?;
+
+    if config.verbose {
+        println!(
+            "Extracted {} frames to {}",
+            images.len(),
+            directory.display()
+        );
+    }
+    Ok(Some(directory))
+}
+
+/// Reads the canvas size, loop count and frame control chunks of an APNG, naming the frames
+/// `000.png`, `001.png`, ... Returns `Ok(None)` for a PNG that is not animated.
+pub fn read_animation(png: &[u8]) -> Result<Option<Animation>, String> {
+    let mut chunks = png.strip_prefix(PNG_SIGNATURE).ok_or("not a PNG file")?;
+    let mut canvas = None;
+    let mut plays = None;
+    let mut frames = Vec::new();
+    while !chunks.is_empty() {
+        let header = chunks.get(..8).ok_or("truncated chunk")?;
+        let length = u32::from_be_bytes(header[0..4].try_into().unwrap()) as usize;
+        let data = chunks.get(8..8 + length).ok_or("truncated chunk")?;
+        match &header[4..8] {
+            b"IHDR" => canvas = Some((be_u32(data, 0)?, be_u32(data, 4)?)),
+            b"acTL" => plays = Some(be_u32(data, 4)?),
+            b"fcTL" => frames.push(read_frame_control(data, frames.len())?),
+            b"IEND" => break,
+            _ => {}
+        }
+        chunks = chunks.get(12 + length..).ok_or("truncated chunk")?;
+    }
+
+    let Some(plays) = plays else {
+        return Ok(None);
+    };
+    let (width, height) = canvas.ok_or("missing IHDR chunk")?;
+    Ok(Some(Animation {
+        width,
+        height,
+        plays,
+        frames,
+    }))
+}
+
+fn read_frame_control(data: &[u8], index: usize) -> Result<Frame, String> {
+    let delay_num = be_u16(data, 20)?;
+    let delay_den = be_u16(data, 22)?;
+    // A denominator of 0 means hundredths of a second.
+    let denominator = if delay_den == 0 { 100 } else { delay_den };
+    let delay_ms = delay_num as f64 * 1000.0 / denominator as f64;
+    let dispose_op = match data.get(24) {
+        Some(0) => "none",
+        Some(1) => "background",
+        Some(2) => "previous",
+        _ => return Err("invalid dispose op".to_string()),
+    };
+    let blend_op = match data.get(25) {
+        Some(0) => "source",
+        Some(1) => "over",
+        _ => return Err("invalid blend op".to_string()),
+    };
+
+    Ok(Frame {
+        file_name: format!("{:03}.png", index),
+        delay_ms,
+        delay_num,
+        delay_den,
+        x_offset: be_u32(data, 12)?,
+        y_offset: be_u32(data, 16)?,
+        width: be_u32(data, 4)?,
+        height: be_u32(data, 8)?,
+        dispose_op,
+        blend_op,
+    })
+}
+
+fn be_u32(data: &[u8], offset: usize) -> Result<u32, String> {
+    let bytes = data.get(offset..offset + 4).ok_or("truncated chunk")?;
+    Ok(u32::from_be_bytes(bytes.try_into().unwrap()))
+}
+
+fn be_u16(data: &[u8], offset: usize) -> Result<u16, String> {
+    let bytes = data.get(offset..offset + 2).ok_or("truncated chunk")?;
+    Ok(u16::from_be_bytes(bytes.try_into().unwrap()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::RgbaImage;
+
+    /// An APNG whose second frame draws a 1x1 pixel over the first one.
+    fn apng() -> Vec<u8> {
+        let mut data = Vec::new();
+        let mut encoder = png::Encoder::new(&mut data, 2, 2);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_animated(2, 3).unwrap();
+        let mut writer = encoder.write_header().unwrap();
+        writer.set_frame_delay(1, 10).unwrap();
+        writer.set_dispose_op(png::DisposeOp::None).unwrap();
+        writer
+            .write_image_data(&[255, 0, 0, 255].repeat(4))
+            .unwrap();
+        writer.set_frame_delay(0, 0).unwrap();
+        writer.set_frame_dimension(1, 1).unwrap();
+        writer.set_frame_position(1, 1).unwrap();
+        writer.set_blend_op(png::BlendOp::Over).unwrap();
+        writer.set_dispose_op(png::DisposeOp::Background).unwrap();
+        writer.write_image_data(&[0, 0, 255, 255]).unwrap();
+        writer.finish().unwrap();
+        data
+    }
+
+    #[test]
+    fn test_frames_directory() {
+        assert_eq!(
+            frames_directory(Path::new("pack/01_1.png")),
+            PathBuf::from("pack/01_1_frames")
+        );
+    }
+
+    #[test]
+    fn test_read_animation() {
+        let actual = read_animation(&apng()).unwrap().unwrap();
+        assert_eq!((actual.width, actual.height, actual.plays), (2, 2, 3));
+        assert_eq!(actual.frames.len(), 2);
+        assert_eq!(
+            actual.frames[0],
+            Frame {
+                file_name: "000.png".to_string(),
+                delay_ms: 100.0,
+                delay_num: 1,
+                delay_den: 10,
+                x_offset: 0,
+                y_offset: 0,
+                width: 2,
+                height: 2,
+                dispose_op: "none",
+                blend_op: "source",
+            }
+        );
+        let second = &actual.frames[1];
+        assert_eq!(second.file_name, "001.png");
+        assert_eq!(second.delay_ms, 0.0);
+        assert_eq!((second.x_offset, second.y_offset), (1, 1));
+        assert_eq!((second.width, second.height), (1, 1));
+        assert_eq!((second.dispose_op, second.blend_op), ("background", "over"));
+
+        let still = encode_png(&RgbaImage::new(2, 2)).unwrap();
+        assert_eq!(read_animation(&still), Ok(None));
+        assert!(read_animation(b"not a png").is_err());
+        assert!(read_animation(&apng()[..40]).is_err());
+    }
+
+    #[test]
+    fn test_explode_file() {
+        let directory = Path::new("Test Explode Frames");
+        std::fs::create_dir_all(directory).unwrap();
+        let path = directory.join("01_1.png");
+        let still_path = directory.join("02_2.png");
+        std::fs::write(&path, apng()).unwrap();
+        std::fs::write(&still_path, encode_png(&RgbaImage::new(2, 2)).unwrap()).unwrap();
+
+        let config = Config::default();
+        let actual = explode_file(&config, &path).map_err(|err| err.to_string());
+        let still = explode_file(&config, &still_path).map_err(|err| err.to_string());
+        let frames_directory = directory.join("01_1_frames");
+        let second = std::fs::read(frames_directory.join("001.png"));
+        let frames_file = std::fs::read(frames_directory.join(FRAMES_FILE_NAME));
+        std::fs::remove_dir_all(directory).unwrap();
+        assert_eq!(actual, Ok(Some(frames_directory)));
+        assert_eq!(still, Ok(None));
+
+        // Frames are composed onto the canvas.
+        let second = image::load_from_memory(&second.unwrap())
+            .unwrap()
+            .to_rgba8();
+        assert_eq!(second.dimensions(), (2, 2));
+        assert_eq!(second.get_pixel(0, 0).0, [255, 0, 0, 255]);
+        assert_eq!(second.get_pixel(1, 1).0, [0, 0, 255, 255]);
+
+        let frames_file: serde_json::Value = serde_json::from_slice(&frames_file.unwrap()).unwrap();
+        assert_eq!(frames_file["width"], 2);
+        assert_eq!(frames_file["plays"], 3);
+        assert_eq!(frames_file["frames"][1]["file_name"], "001.png");
+        assert_eq!(frames_file["frames"][1]["blend_op"], "over");
+    }
+}
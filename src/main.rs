@@ -1,7 +1,319 @@
+mod archive;
+mod cli;
+mod convert;
+mod export;
+mod frames;
+mod manifest;
+mod metadata;
+mod mux;
+mod template;
+mod text;
+mod theme;
+
+use clap::{CommandFactory, Parser};
+use cli::{Cli, Command};
+use export::{ExportPack, ExportSticker};
+use futures::stream::{self, TryStreamExt};
+use manifest::{FileManifest, PackManifest, StickerManifest};
 use regex::Regex;
 use scraper::{Html, Selector};
 use serde_json::Value;
-use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use template::{PathTemplate, PathValues};
+
+const STORE_URL: &str = "https://store.line.me";
+const CDN_URL: &str = "https://stickershop.line-scdn.net";
+const DEFAULT_JOBS: usize = 4;
+const DEFAULT_USER_AGENT: &str = concat!("line-sticker-downloader/", env!("CARGO_PKG_VERSION"));
+const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+const DEFAULT_READ_TIMEOUT: Duration = Duration::from_secs(60);
+const DEFAULT_RETRIES: u32 = 3;
+const DEFAULT_RETRY_MAX_DELAY: Duration = Duration::from_secs(30);
+const RETRY_BASE_DELAY: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone)]
+struct Config {
+    /// Maximum number of sticker files downloaded at the same time within a pack.
+    jobs: usize,
+    /// Client shared by every request so connections are pooled across the whole run.
+    client: reqwest::Client,
+    /// Number of times a request is retried after a network error, 429 or 5xx response.
+    retries: u32,
+    /// Upper bound for the delay between two attempts, including delays requested via `Retry-After`.
+    retry_max_delay: Duration,
+    /// Download files again even if an up-to-date copy already exists.
+    force: bool,
+    /// Directory the sticker pack directories are created in.
+    output: PathBuf,
+    /// Layout of the downloaded files relative to `output`.
+    template: PathTemplate,
+    /// Kinds of sticker files that are downloaded.
+    assets: Vec<Asset>,
+    /// Where the stickers of a product page are downloaded from.
+    source: Source,
+    /// Text rendered onto the base image of message stickers after downloading them.
+    sample_text: Option<String>,
+    /// Font the sample text is rendered with, defaults to a system font.
+    font: Option<PathBuf>,
+    /// Format animated stickers are converted into after downloading them.
+    convert: Option<Convert>,
+    /// Transparency and dithering of converted GIFs.
+    gif_options: convert::GifOptions,
+    /// Quality of converted WebPs, 100 being lossless.
+    webp_quality: u8,
+    /// Delete the downloaded PNG after converting it.
+    delete_original: bool,
+    /// Chat apps every downloaded pack is exported for.
+    export: Vec<Export>,
+    /// Format the animation and sound of sound stickers are combined into.
+    mux_sound: Option<Mux>,
+    /// Write the frames and timing of every animation after downloading it.
+    explode_frames: bool,
+    /// ffmpeg binary used to encode videos.
+    ffmpeg: PathBuf,
+    /// Base URL of the CDN serving the pack archives.
+    cdn_url: String,
+    /// Store language used when building URLs from product and author IDs.
+    lang: String,
+    /// Print the files that would be downloaded instead of downloading them.
+    dry_run: bool,
+    verbose: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            jobs: DEFAULT_JOBS,
+            client: build_client(&HttpOptions::default()).expect("default HTTP client"),
+            retries: DEFAULT_RETRIES,
+            retry_max_delay: DEFAULT_RETRY_MAX_DELAY,
+            force: false,
+            output: PathBuf::from("."),
+            template: PathTemplate::parse(template::DEFAULT_TEMPLATE).expect("default template"),
+            assets: vec![
+                Asset::Image,
+                Asset::Sound,
+                Asset::Popup,
+                Asset::Overlay,
+                Asset::Package,
+            ],
+            source: Source::Html,
+            sample_text: None,
+            font: None,
+            convert: None,
+            gif_options: convert::GifOptions::default(),
+            webp_quality: convert::DEFAULT_WEBP_QUALITY,
+            delete_original: false,
+            export: Vec::new(),
+            mux_sound: None,
+            explode_frames: false,
+            ffmpeg: PathBuf::from("ffmpeg"),
+            cdn_url: CDN_URL.to_string(),
+            lang: "en".to_string(),
+            dry_run: false,
+            verbose: false,
+        }
+    }
+}
+
+/// A response whose body has been read completely.
+#[derive(Debug)]
+struct FetchResponse {
+    status: reqwest::StatusCode,
+    headers: reqwest::header::HeaderMap,
+    body: Vec<u8>,
+}
+
+#[derive(Debug, Default)]
+struct HttpOptions {
+    user_agent: Option<String>,
+    connect_timeout: Option<Duration>,
+    read_timeout: Option<Duration>,
+    /// Proxy used for every request. When unset, the `HTTP_PROXY`, `HTTPS_PROXY`,
+    /// `ALL_PROXY` and `NO_PROXY` environment variables are honoured instead.
+    proxy: Option<String>,
+    headers: Vec<(String, String)>,
+}
+
+/// A sticker as listed on the store page.
+#[derive(Debug)]
+struct StickerData {
+    /// Position of the sticker on the store page, starting at 1.
+    index: usize,
+    id: String,
+    /// The parsed `data-preview` attribute.
+    preview: Value,
+}
+
+impl StickerData {
+    fn sticker_type(&self) -> Option<&str> {
+        self.preview.get("type").and_then(|v| v.as_str())
+    }
+}
+
+/// A kind of file a sticker is made of.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum Asset {
+    /// The animation or, for stickers that are not animated, the static image
+    Image,
+    /// The sound of sound stickers
+    Sound,
+    /// The full-screen effect of popup stickers
+    Popup,
+    /// The static image, also for animated stickers
+    Static,
+    /// The animation of animated stickers
+    Animation,
+    /// The static image shown by LINE versions that cannot play the animation
+    FallbackStatic,
+    /// The resource package of themes
+    Package,
+    /// The frame custom stickers draw around the user's name
+    Overlay,
+}
+
+impl Asset {
+    /// Every file a sticker can be made of, each with a distinct suffix.
+    const ALL_VARIANTS: [Asset; 7] = [
+        Asset::Sound,
+        Asset::Static,
+        Asset::Animation,
+        Asset::FallbackStatic,
+        Asset::Popup,
+        Asset::Overlay,
+        Asset::Package,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Asset::Image => "image",
+            Asset::Sound => "sound",
+            Asset::Popup => "popup",
+            Asset::Static => "static",
+            Asset::Animation => "animation",
+            Asset::FallbackStatic => "fallback-static",
+            Asset::Package => "package",
+            Asset::Overlay => "overlay",
+        }
+    }
+
+    /// Appended to the file name so assets with the same extension don't collide.
+    fn suffix(self) -> &'static str {
+        match self {
+            Asset::Image | Asset::Sound | Asset::Package => "",
+            Asset::Popup => "_popup",
+            Asset::Static => "_static",
+            Asset::Animation => "_anim",
+            Asset::FallbackStatic => "_fallback",
+            Asset::Overlay => "_overlay",
+        }
+    }
+}
+
+/// Kind of product sold in the LINE STORE.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum Category {
+    Sticker,
+    Emoji,
+    Theme,
+}
+
+impl Category {
+    /// First path segment of the shop selling the category, e.g. `stickershop`.
+    fn shop(self) -> &'static str {
+        match self {
+            Category::Sticker => "stickershop",
+            Category::Emoji => "emojishop",
+            Category::Theme => "themeshop",
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Category::Sticker => "sticker",
+            Category::Emoji => "emoji",
+            Category::Theme => "theme",
+        }
+    }
+
+    fn from_url(url: &str) -> Option<Category> {
+        [Category::Sticker, Category::Emoji, Category::Theme]
+            .into_iter()
+            .find(|category| url.contains(&format!("/{}/", category.shop())))
+    }
+}
+
+/// Where the stickers of a pack are downloaded from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum Source {
+    /// Scrape the store page and download every sticker file on its own
+    Html,
+    /// Download the pack archive from the CDN, falling back to the store page
+    Zip,
+}
+
+/// Format animated stickers are converted into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum Convert {
+    /// Animated GIF, playable on platforms without APNG support
+    Gif,
+    /// Lossless WebP for static stickers and animated WebP for animations
+    Webp,
+}
+
+/// Video format the animation and sound of sound stickers are combined into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum Mux {
+    /// H.264 and AAC on a white background, plays nearly everywhere
+    Mp4,
+    /// VP9 with transparency and Opus
+    Webm,
+}
+
+/// Chat app a downloaded pack is exported for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum Export {
+    /// Telegram sticker set: 512px PNG stickers and VP9 WebM animations
+    Telegram,
+    /// WhatsApp sticker packs: 512x512 WebP stickers, contents.json and .wastickers bundles
+    Whatsapp,
+    /// Signal sticker pack: 512x512 PNG/APNG stickers, encrypted for upload with a manifest
+    Signal,
+    /// Discord stickers and emoji: 320x320 PNG/APNG stickers and 128x128 PNG/GIF emoji
+    Discord,
+}
+
+impl Export {
+    /// Name of the directory the export is written into, inside the pack directory.
+    fn name(self) -> &'static str {
+        match self {
+            Export::Telegram => "telegram",
+            Export::Whatsapp => "whatsapp",
+            Export::Signal => "signal",
+            Export::Discord => "discord",
+        }
+    }
+}
+
+impl Mux {
+    fn extension(self) -> &'static str {
+        match self {
+            Mux::Mp4 => "mp4",
+            Mux::Webm => "webm",
+        }
+    }
+}
+
+impl Convert {
+    fn extension(self) -> &'static str {
+        match self {
+            Convert::Gif => "gif",
+            Convert::Webp => "webp",
+        }
+    }
+}
 
 #[derive(Debug, serde::Deserialize)]
 struct Item {
@@ -18,51 +330,284 @@ struct SearchResponse {
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let args: Vec<String> = std::env::args().collect();
+    let cli = Cli::parse();
 
-    process_args("https://store.line.me", &args).await?;
+    run(STORE_URL, CDN_URL, cli).await?;
 
     Ok(())
 }
 
-async fn process_args(base_url: &str, args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
-    if args.len() < 2 {
-        return Err("Usage: line-sticker-downloader <url1> <url2> ...".into());
+async fn run(base_url: &str, cdn_url: &str, cli: Cli) -> Result<(), Box<dyn std::error::Error>> {
+    if let Command::Completions { shell } = cli.command {
+        let mut command = Cli::command();
+        let name = command.get_name().to_string();
+        clap_complete::generate(shell, &mut command, name, &mut std::io::stdout());
+        return Ok(());
     }
 
-    for arg in &args[1..] {
-        let result = if url::Url::parse(arg).is_ok() {
-            download_stickers(arg).await
-        } else {
-            download_stickers_from_search_query(base_url, arg).await
-        };
+    let config = Config {
+        cdn_url: cdn_url.to_string(),
+        ..cli.options.config()?
+    };
+    if let Err(err) = run_command(&config, base_url, &cli.command).await {
+        return Err(format!("Failed to fetch stickers: {}", err).into());
+    }
+
+    Ok(())
+}
 
-        if let Err(err) = result {
-            return Err(format!("Failed to fetch stickers: {}", err).into());
+async fn run_command(
+    config: &Config,
+    base_url: &str,
+    command: &Command,
+) -> Result<(), Box<dyn std::error::Error>> {
+    match command {
+        Command::Download { inputs } => {
+            for input in inputs {
+                download_stickers(config, &product_url(base_url, &config.lang, input)?).await?;
+            }
+        }
+        Command::Search { queries, category } => {
+            for query in queries {
+                download_stickers_from_search_query(config, base_url, query, *category).await?;
+            }
+        }
+        Command::Author { authors } => {
+            for author in authors {
+                download_stickers(config, &author_url(base_url, &config.lang, author)?).await?;
+            }
+        }
+        Command::Info { inputs } => {
+            for input in inputs {
+                print_pack_info(config, &product_url(base_url, &config.lang, input)?).await?;
+            }
         }
+        Command::Completions { .. } => {}
     }
 
     Ok(())
 }
 
+/// Turns any of the ways a sticker pack, emoji pack or theme can be referred to into the canonical URL
+/// of its product page: bare product IDs, `line://shop/detail/<id>` deep links,
+/// `https://line.me/S/sticker/<id>` share links and store URLs with or without scheme,
+/// language or query. Other URLs are used as they are.
+fn product_url(base_url: &str, lang: &str, input: &str) -> Result<String, String> {
+    let input = input.trim();
+    let canonical_url = |category: Category, id: &str, url_lang: Option<&str>| {
+        format!(
+            "{}/{}/product/{}/{}",
+            base_url,
+            category.shop(),
+            id,
+            url_lang.unwrap_or(lang)
+        )
+    };
+
+    if is_id(input) {
+        return Ok(canonical_url(Category::Sticker, input, None));
+    }
+    if is_emoji_id(input) {
+        return Ok(canonical_url(Category::Emoji, input, None));
+    }
+    if is_theme_id(input) {
+        return Ok(canonical_url(Category::Theme, input, None));
+    }
+
+    let link_regex = Regex::new(
+        r"^(?:line://shop/(?:sticker/)?detail/|(?:https?://)?line\.me/(?:S/sticker/|R/shop/(?:sticker/)?detail/))(\d+)(?:[/?#]|$)",
+    )
+    .unwrap();
+    let emoji_link_regex = Regex::new(
+        r"^(?:line://shop/emoji/detail/|(?:https?://)?line\.me/S/emoji/?\?(?:[^#]*&)?id=)([0-9a-f]{24})(?:[/?&#]|$)",
+    )
+    .unwrap();
+    let store_regex = Regex::new(
+        r"^(?:https?://)?(?:m\.)?store\.line\.me/(stickershop|emojishop|themeshop)/product/(\d+|[0-9a-f]{24}|[0-9a-f]{8}(?:-[0-9a-f]{4}){3}-[0-9a-f]{12})(?:/([A-Za-z]{2}(?:-[A-Za-z]+)?))?/?(?:[?#]|$)",
+    )
+    .unwrap();
+    let query_lang_regex = Regex::new(r"[?&]lang=([A-Za-z]{2}(?:-[A-Za-z]+)?)(?:&|#|$)").unwrap();
+    let query_lang = query_lang_regex
+        .captures(input)
+        .and_then(|captures| captures.get(1))
+        .map(|m| m.as_str());
+
+    if let Some(captures) = link_regex.captures(input) {
+        Ok(canonical_url(Category::Sticker, &captures[1], query_lang))
+    } else if let Some(captures) = emoji_link_regex.captures(input) {
+        Ok(canonical_url(Category::Emoji, &captures[1], query_lang))
+    } else if let Some(captures) = store_regex.captures(input) {
+        let category = match &captures[1] {
+            "emojishop" => Category::Emoji,
+            "themeshop" => Category::Theme,
+            _ => Category::Sticker,
+        };
+        let path_lang = captures.get(3).map(|m| m.as_str());
+        Ok(canonical_url(
+            category,
+            &captures[2],
+            path_lang.or(query_lang),
+        ))
+    } else if url::Url::parse(input).is_ok() {
+        Ok(input.to_string())
+    } else {
+        Err(format!("{} is neither a URL nor a product ID", input))
+    }
+}
+
+/// Turns an author URL or a bare author ID into the URL of the author page.
+fn author_url(base_url: &str, lang: &str, input: &str) -> Result<String, String> {
+    if url::Url::parse(input).is_ok() {
+        Ok(input.to_string())
+    } else if is_id(input) {
+        Ok(format!(
+            "{}/stickershop/author/{}/{}",
+            base_url, input, lang
+        ))
+    } else {
+        Err(format!("{} is neither a URL nor an author ID", input))
+    }
+}
+
+fn is_id(input: &str) -> bool {
+    !input.is_empty() && input.chars().all(|c| c.is_ascii_digit())
+}
+
+/// Emoji products are identified by 24 hexadecimal digits instead of a number.
+fn is_emoji_id(input: &str) -> bool {
+    input.len() == 24 && input.chars().all(|c| matches!(c, '0'..='9' | 'a'..='f'))
+}
+
+fn is_author_url(url: &str) -> bool {
+    [Category::Sticker, Category::Emoji, Category::Theme]
+        .iter()
+        .any(|category| url.contains(&format!("/{}/author/", category.shop())))
+}
+
+/// Theme products are identified by a UUID.
+fn is_theme_id(input: &str) -> bool {
+    let regex =
+        Regex::new(r"^[0-9a-f]{8}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{12}$").unwrap();
+    regex.is_match(input)
+}
+
+fn build_client(options: &HttpOptions) -> Result<reqwest::Client, Box<dyn std::error::Error>> {
+    let mut headers = reqwest::header::HeaderMap::new();
+    for (name, value) in &options.headers {
+        headers.append(
+            reqwest::header::HeaderName::from_bytes(name.as_bytes())?,
+            reqwest::header::HeaderValue::from_str(value)?,
+        );
+    }
+
+    let mut builder = reqwest::Client::builder()
+        .user_agent(options.user_agent.as_deref().unwrap_or(DEFAULT_USER_AGENT))
+        .connect_timeout(options.connect_timeout.unwrap_or(DEFAULT_CONNECT_TIMEOUT))
+        .read_timeout(options.read_timeout.unwrap_or(DEFAULT_READ_TIMEOUT))
+        .default_headers(headers);
+
+    if let Some(proxy) = &options.proxy {
+        builder = builder.proxy(reqwest::Proxy::all(proxy)?);
+    }
+
+    Ok(builder.build()?)
+}
+
+/// Sends a GET request and reads the whole body, retrying network errors, 429 and 5xx
+/// responses with exponential backoff. Other responses are returned as they are.
+async fn fetch(config: &Config, url: &str) -> Result<FetchResponse, Box<dyn std::error::Error>> {
+    let mut attempt = 0;
+    loop {
+        let (error, retry_after) = match fetch_once(config, url).await {
+            Ok(response) if is_retryable_status(response.status) => {
+                let retry_after = parse_retry_after(&response.headers);
+                (format!("HTTP status {}", response.status), retry_after)
+            }
+            Ok(response) => return Ok(response),
+            Err(err) if err.is_builder() => return Err(err.into()),
+            Err(err) => (err.to_string(), None),
+        };
+
+        attempt += 1;
+        if attempt > config.retries {
+            return Err(
+                format!("Giving up on {} after {} attempts: {}", url, attempt, error).into(),
+            );
+        }
+
+        let delay = retry_after
+            .unwrap_or_else(|| backoff_delay(attempt))
+            .min(config.retry_max_delay);
+        tokio::time::sleep(delay).await;
+    }
+}
+
+async fn fetch_once(config: &Config, url: &str) -> Result<FetchResponse, reqwest::Error> {
+    let response = config.client.get(url).send().await?;
+    let status = response.status();
+    let headers = response.headers().clone();
+    let body = response.bytes().await?.to_vec();
+
+    Ok(FetchResponse {
+        status,
+        headers,
+        body,
+    })
+}
+
+fn is_retryable_status(status: reqwest::StatusCode) -> bool {
+    status == reqwest::StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+}
+
+/// Parses a `Retry-After` header given either in seconds or as an HTTP date.
+fn parse_retry_after(headers: &reqwest::header::HeaderMap) -> Option<Duration> {
+    let value = headers
+        .get(reqwest::header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim();
+
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    let date = httpdate::parse_http_date(value).ok()?;
+    Some(
+        date.duration_since(std::time::SystemTime::now())
+            .unwrap_or(Duration::ZERO),
+    )
+}
+
+/// Exponential backoff with jitter: a random delay between half and all of
+/// `RETRY_BASE_DELAY * 2^(attempt - 1)`.
+fn backoff_delay(attempt: u32) -> Duration {
+    let delay = RETRY_BASE_DELAY.saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)));
+    delay.mul_f64(0.5 + fastrand::f64() / 2.0)
+}
+
 async fn download_stickers_from_search_query(
+    config: &Config,
     base_url: &str,
     search_query: &str,
+    category: Category,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let limit = 36;
     let mut offset = 0;
 
     loop {
         let url = format!(
-            "{}/api/search/sticker?category=sticker&type=ALL&offset={}&limit={}&includeFacets=false&query={}",
+            "{}/api/search/{}?category={}&type=ALL&offset={}&limit={}&includeFacets=false&query={}",
             base_url,
+            category.name(),
+            category.name(),
             offset,
             limit,
             search_query,
         );
 
-        let response = reqwest::get(&url).await?;
-        let json: SearchResponse = response.json().await?;
+        let response = fetch(config, &url).await?;
+        ensure_success(&url, &response)?;
+        let json: SearchResponse = serde_json::from_slice(&response.body)?;
         let total_count = json.total_count;
         let items = json.items;
 
@@ -70,7 +615,7 @@ async fn download_stickers_from_search_query(
             "Fetching Stickers for query: {} Page: {}",
             search_query, offset
         );
-        download_items(base_url, items).await?;
+        download_items(config, base_url, items).await?;
 
         offset += limit;
         if offset >= total_count {
@@ -80,56 +625,406 @@ async fn download_stickers_from_search_query(
 }
 
 async fn download_items(
+    config: &Config,
     base_url: &str,
     items: Vec<Item>,
 ) -> Result<(), Box<dyn std::error::Error>> {
     for item in items {
         let url = format!("{}{}", base_url, item.product_url);
-        download_stickers(&url).await?
+        download_stickers(config, &url).await?
+    }
+
+    Ok(())
+}
+
+async fn fetch_document(config: &Config, url: &str) -> Result<Html, Box<dyn std::error::Error>> {
+    let response = fetch(config, url).await?;
+    ensure_success(url, &response)?;
+    Ok(Html::parse_document(&String::from_utf8_lossy(
+        &response.body,
+    )))
+}
+
+async fn print_pack_info(config: &Config, url: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let document = fetch_document(config, url).await?;
+    let title = extract_title_from_document(&document)?;
+    let sticker_data = extract_sticker_data_from_document(&document)?;
+
+    let mut types: BTreeMap<&str, usize> = BTreeMap::new();
+    for sticker in &sticker_data {
+        *types
+            .entry(sticker.sticker_type().unwrap_or("unknown"))
+            .or_default() += 1;
+    }
+    let types: Vec<String> = types
+        .iter()
+        .map(|(sticker_type, count)| format!("{} {}", count, sticker_type))
+        .collect();
+
+    println!("Title: {}", title);
+    println!("URL: {}", url);
+    println!("Stickers: {} ({})", sticker_data.len(), types.join(", "));
+
+    if config.verbose {
+        for sticker in &sticker_data {
+            for (_, url) in extract_sticker_urls(&sticker.preview, &config.assets) {
+                println!("  {:>3} {} {}", sticker.index, sticker.id, url);
+            }
+        }
     }
 
     Ok(())
 }
 
-async fn download_stickers(initial_url: &str) -> Result<(), Box<dyn std::error::Error>> {
+async fn download_stickers(
+    config: &Config,
+    initial_url: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
     let mut url_queue: VecDeque<String> = VecDeque::new();
     url_queue.push_back(initial_url.to_string());
 
     while let Some(url) = url_queue.pop_back() {
-        let response = reqwest::get(&url).await?.text().await?;
-        let document = Html::parse_document(&response);
-
-        if url.contains("/stickershop/author/") {
+        if is_author_url(&url) {
+            let document = fetch_document(config, &url).await?;
             println!("Fetching Stickers from: {}", url);
 
             url_queue.extend(extract_author_page_urls(url, document)?);
             continue;
         }
 
-        let title = extract_title_from_document(&document)?;
-        println!("Downloading {}", title);
+        match Category::from_url(&url).unwrap_or(Category::Sticker) {
+            Category::Theme => theme::download_theme(config, &url).await?,
+            category => download_pack(config, &url, category).await?,
+        }
+    }
+
+    Ok(())
+}
 
-        let directory = sanitize_directory_name(&title);
-        for (id, value) in extract_sticker_data_from_document(&document)? {
-            if let Some(url) = value.get("soundUrl").and_then(|v| v.as_str()) {
-                if !url.is_empty() {
-                    download_file(url, &id, &directory).await?;
-                }
+/// Downloads a sticker or emoji pack, from its archive if requested, otherwise from its
+/// store page.
+async fn download_pack(
+    config: &Config,
+    url: &str,
+    category: Category,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let is_sticker_product = category == Category::Sticker;
+    if config.source == Source::Zip && is_sticker_product {
+        if let (Some(pack_id), lang) = extract_product_id_and_lang(url) {
+            let lang = lang.unwrap_or(&config.lang);
+            if archive::download_pack(config, url, pack_id, lang).await? {
+                return Ok(());
             }
+            println!(
+                "No pack archive found for {}, falling back to the store page",
+                pack_id
+            );
+        }
+    }
 
-            if let Some(url) = value.get("animationUrl").and_then(|v| v.as_str()) {
-                if !url.is_empty() {
-                    download_file(url, &id, &directory).await?;
-                } else if let Some(url) = value.get("staticUrl").and_then(|v| v.as_str()) {
-                    download_file(url, &id, &directory).await?;
-                }
+    let document = fetch_document(config, url).await?;
+
+    let (pack_id, lang) = extract_product_id_and_lang(url);
+    let lang = lang.unwrap_or(&config.lang);
+    // The CDN only publishes metadata for sticker packs.
+    let product_info = match pack_id {
+        Some(pack_id) if is_sticker_product => {
+            metadata::fetch_product_info(config, pack_id).await?
+        }
+        _ => None,
+    };
+
+    let title = match product_info.as_ref().and_then(|info| info.title(lang)) {
+        Some(title) => title,
+        None => extract_title_from_document(&document)?,
+    };
+    println!("Downloading {}", title);
+
+    let author = product_info
+        .as_ref()
+        .and_then(|info| info.author(lang))
+        .or_else(|| extract_author_from_document(&document));
+    let sticker_data = extract_sticker_data_from_document(&document)?;
+
+    let mut sticker_files = Vec::new();
+    for sticker in &sticker_data {
+        let mut files = Vec::new();
+        for (asset, url) in extract_sticker_urls(&sticker.preview, &config.assets) {
+            let ext =
+                extract_file_extension(url).ok_or("Could not extract file extension from url")?;
+            let path = config.template.render(&PathValues {
+                pack_id: pack_id.unwrap_or_default(),
+                title: &title,
+                author: author.as_deref().unwrap_or_default(),
+                lang,
+                sticker_id: &sticker.id,
+                index: sticker.index,
+                sticker_type: sticker.sticker_type().unwrap_or_default(),
+                suffix: asset.suffix(),
+                ext: &ext,
+            });
+            files.push((asset, url.to_string(), config.output.join(path)));
+        }
+        sticker_files.push(files);
+    }
+    ensure_unique_paths(sticker_files.iter().flatten().map(|(_, _, path)| path))?;
+
+    let downloads = sticker_data
+        .iter()
+        .zip(&sticker_files)
+        .flat_map(|(sticker, files)| {
+            files
+                .iter()
+                .map(move |(_, url, path)| Ok((url.as_str(), sticker.id.as_str(), path)))
+        });
+    stream::iter(downloads)
+        .try_for_each_concurrent(config.jobs, |(url, id, path)| {
+            download_file(config, url, id, path)
+        })
+        .await?;
+
+    if config.dry_run {
+        return Ok(());
+    }
+
+    if let Some(sample_text) = &config.sample_text {
+        render_message_samples(config, sample_text, &sticker_data, &sticker_files)?;
+    }
+
+    let stickers = sticker_data
+        .iter()
+        .map(|sticker| {
+            let info = product_info
+                .as_ref()
+                .and_then(|info| info.sticker(&sticker.id));
+            StickerManifest {
+                id: sticker.id.clone(),
+                order: sticker.index,
+                sticker_type: sticker
+                    .sticker_type()
+                    .map(String::from)
+                    .or_else(|| product_info.as_ref()?.sticker_type()),
+                width: info.and_then(|info| info.width),
+                height: info.and_then(|info| info.height),
+                text_area: text::extract_text_area(&sticker.preview),
+                urls: extract_preview_urls(&sticker.preview),
+                files: Vec::new(),
             }
+        })
+        .collect();
+    postprocess_pack(
+        config,
+        PackManifest {
+            pack_id: pack_id.map(String::from),
+            title,
+            author,
+            description: extract_description_from_document(&document),
+            lang: lang.to_string(),
+            source_url: url.to_string(),
+            downloaded_at: humantime::format_rfc3339_seconds(std::time::SystemTime::now())
+                .to_string(),
+            stickers,
+        },
+        &sticker_files,
+    )
+    .await
+}
+
+/// Runs everything that follows the download of a pack: the exports, muxing sound
+/// stickers, extracting frames and converting files, then writes the manifest.
+///
+/// `sticker_files` holds the asset, source url and path of every file of each sticker of
+/// `pack`, in the same order. The files of each sticker are added to the manifest.
+async fn postprocess_pack(
+    config: &Config,
+    mut pack: PackManifest,
+    sticker_files: &[Vec<(Asset, String, PathBuf)>],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let paths = || sticker_files.iter().flatten().map(|(_, _, path)| path);
+    let Some(directory) = manifest::pack_directory(paths().map(PathBuf::as_path)) else {
+        return Ok(());
+    };
+
+    if !config.export.is_empty() {
+        let stickers = pack
+            .stickers
+            .iter()
+            .zip(sticker_files)
+            .filter_map(|(sticker, files)| {
+                let files = files.iter().map(|(asset, _, path)| (*asset, path));
+                ExportSticker::from_files(&sticker.id, sticker.order, files)
+            })
+            .collect();
+        export::export_pack(
+            config,
+            &ExportPack {
+                pack_id: pack.pack_id.as_deref(),
+                title: &pack.title,
+                author: pack.author.as_deref(),
+                directory: &directory,
+                stickers,
+            },
+        )
+        .await?;
+    }
+
+    let mut muxed = HashMap::new();
+    if let Some(format) = config.mux_sound {
+        for files in sticker_files {
+            let files = files.iter().map(|(asset, _, path)| (*asset, path));
+            muxed.extend(mux::mux_sticker(config, format, files).await?);
+        }
+    }
+
+    if config.explode_frames {
+        frames::explode_files(config, paths())?;
+    }
+
+    let converted = match config.convert {
+        Some(format) => convert::convert_files(config, format, paths())?,
+        None => HashMap::new(),
+    };
+
+    for (sticker, files) in pack.stickers.iter_mut().zip(sticker_files) {
+        sticker.files = files
+            .iter()
+            .flat_map(|(asset, url, path)| {
+                convert::output_files(config, &converted, path)
+                    .into_iter()
+                    .chain(muxed.get(path))
+                    .map(|path| FileManifest::from_file(&directory, path, url, asset.name()))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Result<_, _>>()?;
+    }
+
+    let path = manifest::write_manifest(&directory, &pack)?;
+    if config.verbose {
+        println!("Wrote {}", path.display());
+    }
+
+    Ok(())
+}
+
+/// Renders the sample text onto the base image of every message sticker, next to the
+/// downloaded image.
+fn render_message_samples(
+    config: &Config,
+    sample_text: &str,
+    sticker_data: &[StickerData],
+    sticker_files: &[Vec<(Asset, String, PathBuf)>],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut font = None;
+    for (sticker, files) in sticker_data.iter().zip(sticker_files) {
+        let is_message = sticker
+            .sticker_type()
+            .and_then(text::TextSticker::from_type)
+            == Some(text::TextSticker::Message);
+        let image = files
+            .iter()
+            .find(|(asset, _, _)| matches!(asset, Asset::Image | Asset::Static));
+        let (true, Some((_, _, image_path))) = (is_message, image) else {
+            continue;
+        };
+
+        if font.is_none() {
+            font = Some(text::load_font(config.font.as_deref())?);
+        }
+        let sample_path = text::sample_path(image_path);
+        text::render_sample(
+            image_path,
+            &sample_path,
+            sample_text,
+            text::extract_text_area(&sticker.preview),
+            font.as_ref().expect("font is loaded above"),
+        )?;
+        if config.verbose {
+            println!("Rendered {}", sample_path.display());
         }
     }
 
     Ok(())
 }
 
+/// Returns every non-empty url of a sticker's `data-preview`, keyed by its field name.
+fn extract_preview_urls(value: &Value) -> BTreeMap<String, String> {
+    let Some(object) = value.as_object() else {
+        return BTreeMap::new();
+    };
+
+    object
+        .iter()
+        .filter(|(key, _)| key.ends_with("Url"))
+        .filter_map(|(key, value)| Some((key.clone(), value.as_str()?.to_string())))
+        .filter(|(_, url)| !url.is_empty())
+        .collect()
+}
+
+/// Fails if the template renders the same path for two files, which would otherwise
+/// make them overwrite each other.
+fn ensure_unique_paths<'a>(
+    paths: impl Iterator<Item = &'a PathBuf>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut seen = HashSet::new();
+    for path in paths {
+        if !seen.insert(path) {
+            return Err(format!(
+                "The template renders the same path {} for multiple files. Add {{sticker_id}} or {{suffix}} to the template",
+                path.display()
+            )
+            .into());
+        }
+    }
+
+    Ok(())
+}
+
+/// Extracts the product ID and, if present, the language from a product URL like
+/// `https://store.line.me/stickershop/product/28170905/en`.
+fn extract_product_id_and_lang(url: &str) -> (Option<&str>, Option<&str>) {
+    let product_regex = Regex::new(r"/product/([^/?#]+)(?:/([^/?#]+))?").unwrap();
+    match product_regex.captures(url) {
+        Some(captures) => (
+            captures.get(1).map(|m| m.as_str()),
+            captures.get(2).map(|m| m.as_str()),
+        ),
+        None => (None, None),
+    }
+}
+
+/// Returns the urls of the files that make up a sticker, limited to the given assets:
+/// its sound, its animation, falling back to the base image without text of custom and
+/// message stickers and then to the static image, each image variant on its own, the
+/// full-screen effect of popup stickers and the name frame of custom stickers.
+fn extract_sticker_urls<'a>(value: &'a Value, assets: &[Asset]) -> Vec<(Asset, &'a str)> {
+    let get_url = |key: &str| {
+        value
+            .get(key)
+            .and_then(|v| v.as_str())
+            .filter(|url| !url.is_empty())
+    };
+
+    let urls = [
+        (Asset::Sound, get_url("soundUrl")),
+        (
+            Asset::Image,
+            get_url("animationUrl")
+                .or_else(|| get_url("baseUrl"))
+                .or_else(|| get_url("staticUrl")),
+        ),
+        (Asset::Static, get_url("staticUrl")),
+        (Asset::Animation, get_url("animationUrl")),
+        (Asset::FallbackStatic, get_url("fallbackStaticUrl")),
+        (Asset::Popup, get_url("popupUrl")),
+        (Asset::Overlay, get_url("overlayUrl")),
+    ];
+
+    urls.into_iter()
+        .filter(|(asset, _)| assets.contains(asset))
+        .filter_map(|(asset, url)| Some((asset, url?)))
+        .collect()
+}
+
 fn extract_author_page_urls(
     url: String,
     document: Html,
@@ -190,7 +1085,9 @@ fn sanitize_directory_name(name: &str) -> String {
 }
 
 fn extract_title_from_document(document: &Html) -> Result<String, String> {
-    let selector = Selector::parse(r#"p[data-test="sticker-name-title"]"#).unwrap();
+    let selector =
+        Selector::parse(r#"p[data-test="sticker-name-title"], p[data-test="emoji-name-title"], p[data-test="theme-name-title"]"#)
+            .unwrap();
 
     if let Some(element) = document.select(&selector).next() {
         let text = element.text().next().unwrap_or_default();
@@ -200,16 +1097,47 @@ fn extract_title_from_document(document: &Html) -> Result<String, String> {
     }
 }
 
-fn extract_sticker_data_from_document(document: &Html) -> Result<HashMap<String, Value>, String> {
+fn extract_author_from_document(document: &Html) -> Option<String> {
+    let selector =
+        Selector::parse(r#"a[data-test="sticker-author"], a[data-test="emoji-author"], a[data-test="theme-author"]"#).unwrap();
+
+    document
+        .select(&selector)
+        .next()
+        .map(|element| element.text().collect::<String>().trim().to_string())
+        .filter(|author| !author.is_empty())
+}
+
+fn extract_description_from_document(document: &Html) -> Option<String> {
+    let selector =
+        Selector::parse(r#"p[data-test="sticker-description"], p[data-test="emoji-description"], p[data-test="theme-description"]"#)
+            .unwrap();
+
+    document
+        .select(&selector)
+        .next()
+        .map(|element| element.text().collect::<String>().trim().to_string())
+        .filter(|description| !description.is_empty())
+}
+
+/// Returns the stickers in the order they are shown on the store page.
+fn extract_sticker_data_from_document(document: &Html) -> Result<Vec<StickerData>, String> {
     let selector = Selector::parse("li.FnStickerPreviewItem").unwrap();
 
-    let mut sticker_data_map: HashMap<String, Value> = HashMap::new();
+    let mut sticker_data: Vec<StickerData> = Vec::new();
+    let mut seen_ids: HashSet<String> = HashSet::new();
     for element in document.select(&selector) {
         if let Some(data_preview) = element.value().attr("data-preview") {
             match serde_json::from_str::<Value>(data_preview) {
                 Ok(json) => {
                     if let Some(id) = json.get("id").and_then(|v| v.as_str()) {
-                        sticker_data_map.insert(id.to_string(), json);
+                        if seen_ids.insert(id.to_string()) {
+                            sticker_data.push(StickerData {
+                                index: sticker_data.len() + 1,
+                                id: id.to_string(),
+                                preview: json,
+                            });
+                        }
                     }
                 }
                 Err(err) => {
@@ -219,26 +1147,159 @@ fn extract_sticker_data_from_document(document: &Html) -> Result<HashMap<String,
         }
     }
 
-    Ok(sticker_data_map)
+    Ok(sticker_data)
 }
 
 async fn download_file(
+    config: &Config,
     url: &str,
-    file_name: &str,
-    directory: &str,
+    sticker_id: &str,
+    file_path: &Path,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    if !std::path::Path::new(directory).exists() {
+    let extension =
+        extract_file_extension(url).ok_or("Could not extract file extension from url")?;
+
+    if config.dry_run {
+        println!("Would download {} to {}", url, file_path.display());
+        return Ok(());
+    }
+
+    if let Some(directory) = file_path.parent().filter(|d| !d.exists()) {
         std::fs::create_dir_all(directory)?;
     }
 
-    if let Some(extension) = extract_file_extension(url) {
-        let file_path = format!("{}/{}.{}", directory, file_name, extension);
-        let response = reqwest::get(url).await?;
-        let bytes = response.bytes().await?;
-        std::fs::write(&file_path, bytes)?;
+    if !config.force && is_file_up_to_date(config, url, file_path).await {
+        if config.verbose {
+            println!("Skipping {} (up to date)", file_path.display());
+        }
+        return Ok(());
+    }
+
+    let response = fetch(config, url)
+        .await
+        .and_then(|response| {
+            ensure_success(url, &response)?;
+            validate_file_contents(&extension, &response)?;
+            Ok(response)
+        })
+        .map_err(|err| format!("Failed to download sticker {}: {}", sticker_id, err))?;
+    write_file_atomically(file_path, &response.body)?;
+
+    if config.verbose {
+        println!("Downloaded {}", file_path.display());
+    }
+    Ok(())
+}
+
+/// Checks an existing file with a HEAD request that is conditional on the file's
+/// modification time. Unless the server answers `304 Not Modified`, the `Content-Length`
+/// and `Last-Modified` headers have to match the file, and at least one of them has to be
+/// present. Any failure counts as outdated so the file is downloaded again.
+async fn is_file_up_to_date(config: &Config, url: &str, file_path: &Path) -> bool {
+    let Ok(metadata) = std::fs::metadata(file_path) else {
+        return false;
+    };
+    let Ok(modified) = metadata.modified() else {
+        return false;
+    };
+
+    let request = config.client.head(url).header(
+        reqwest::header::IF_MODIFIED_SINCE,
+        httpdate::fmt_http_date(modified),
+    );
+    match request.send().await {
+        Ok(response) if response.status() == reqwest::StatusCode::NOT_MODIFIED => true,
+        Ok(response) if response.status().is_success() => {
+            matches_validators(response.headers(), metadata.len(), modified)
+        }
+        _ => false,
+    }
+}
+
+/// Whether the `Content-Length` and `Last-Modified` headers match a file of `len` bytes
+/// modified at `modified`. Headers without either say nothing about the file.
+fn matches_validators(
+    headers: &reqwest::header::HeaderMap,
+    len: u64,
+    modified: std::time::SystemTime,
+) -> bool {
+    let header =
+        |name: reqwest::header::HeaderName| headers.get(name).and_then(|v| v.to_str().ok());
+    let content_length = header(reqwest::header::CONTENT_LENGTH);
+    let last_modified = header(reqwest::header::LAST_MODIFIED);
+    if content_length.is_none() && last_modified.is_none() {
+        return false;
+    }
+
+    if let Some(content_length) = content_length {
+        if content_length.parse::<u64>().ok() != Some(len) {
+            return false;
+        }
+    }
+
+    if let Some(last_modified) = last_modified {
+        match httpdate::parse_http_date(last_modified) {
+            Ok(last_modified) if last_modified <= modified => {}
+            _ => return false,
+        }
+    }
+
+    true
+}
+
+/// Writes to a temporary file next to the destination and renames it afterwards,
+/// so an interrupted run never leaves a truncated file behind.
+fn write_file_atomically(file_path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let mut temp_path = file_path.as_os_str().to_owned();
+    temp_path.push(".part");
+    std::fs::write(&temp_path, contents)?;
+    std::fs::rename(&temp_path, file_path).inspect_err(|_| {
+        let _ = std::fs::remove_file(&temp_path);
+    })
+}
+
+fn ensure_success(url: &str, response: &FetchResponse) -> Result<(), Box<dyn std::error::Error>> {
+    if response.status.is_success() {
+        Ok(())
+    } else {
+        Err(format!("{} responded with HTTP status {}", url, response.status).into())
+    }
+}
+
+/// Checks that a downloaded file is what its extension claims to be, so that error pages
+/// served with a success status never end up saved as stickers.
+fn validate_file_contents(
+    extension: &str,
+    response: &FetchResponse,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let content_type = response
+        .headers
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    if content_type.starts_with("text/") || content_type.starts_with("application/json") {
+        return Err(format!(
+            "Unexpected content type {} for a .{} file",
+            content_type, extension
+        )
+        .into());
+    }
+
+    let body = &response.body;
+    let is_valid = match extension.to_ascii_lowercase().as_str() {
+        "png" => body.starts_with(b"\x89PNG\r\n\x1a\n"),
+        "m4a" | "mp4" => body.len() >= 8 && &body[4..8] == b"ftyp",
+        "gif" => body.starts_with(b"GIF87a") || body.starts_with(b"GIF89a"),
+        "jpg" | "jpeg" => body.starts_with(b"\xff\xd8\xff"),
+        "webp" => body.len() >= 12 && body.starts_with(b"RIFF") && &body[8..12] == b"WEBP",
+        "zip" => body.starts_with(b"PK\x03\x04"),
+        _ => true,
+    };
+
+    if is_valid {
         Ok(())
     } else {
-        Err("Could not extract file extension from url".into())
+        Err(format!("Downloaded content is not a valid .{} file", extension).into())
     }
 }
 
@@ -255,6 +1316,9 @@ fn extract_file_extension(url: &str) -> Option<String> {
 mod tests {
     use super::*;
 
+    const PNG_BYTES: &[u8] = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+    const M4A_BYTES: &[u8] = b"\0\0\0\x1cftypM4A \0\0\0\0";
+
     #[test]
     fn test_sanitize_directory_name() {
         // Should replace '/' with '_'
@@ -294,36 +1358,115 @@ mod tests {
     }
 
     #[test]
-    fn test_extract_sticker_data_from_document() {
+    fn test_extract_author_from_document() {
         let document = Html::parse_document(
-            r#"<ul>
-                    <li class="for_testing"></li>
-                    <li class="mdCMN09Li FnStickerPreviewItem animation_sound-sticker " data-preview="{ &quot;type&quot; : &quot;animation_sound&quot;, &quot;id&quot; : &quot;20578528&quot;, &quot;staticUrl&quot; : &quot;https://stickershop.line-scdn.net/stickershop/v1/sticker/20578528/iPhone/sticker@2x.png?v=1&quot;, &quot;fallbackStaticUrl&quot; : &quot;https://stickershop.line-scdn.net/stickershop/v1/sticker/20578528/iPhone/sticker@2x.png?v=1&quot;, &quot;animationUrl&quot; : &quot;https://stickershop.line-scdn.net/stickershop/v1/sticker/20578528/iPhone/sticker_animation@2x.png?v=1&quot;, &quot;popupUrl&quot; : &quot;&quot;, &quot;soundUrl&quot; : &quot;https://stickershop.line-scdn.net/stickershop/v1/sticker/20578528/android/sticker_sound.m4a?v=1&quot; }" data-test="sticker-item"></li>
-                    <li class="for_testing" data-preview="{ &quot;type&quot; : &quot;animation&quot;, &quot;id&quot; : &quot;1&quot;}"></li>
-                    <li class="mdCMN09Li FnStickerPreviewItem animation-sticker " data-preview="{ &quot;type&quot; : &quot;animation&quot;, &quot;id&quot; : &quot;651763951&quot;, &quot;staticUrl&quot; : &quot;https://stickershop.line-scdn.net/stickershop/v1/sticker/651763951/iPhone/sticker@2x.png?v=2&quot;, &quot;fallbackStaticUrl&quot; : &quot;https://stickershop.line-scdn.net/stickershop/v1/sticker/651763951/iPhone/sticker@2x.png?v=2&quot;, &quot;animationUrl&quot; : &quot;https://stickershop.line-scdn.net/stickershop/v1/sticker/651763951/iPhone/sticker_animation@2x.png?v=2&quot;, &quot;popupUrl&quot; : &quot;&quot;, &quot;soundUrl&quot; : &quot;&quot; }" data-test="sticker-item">
-                    <li class="for_testing"></li>
-                </ul>"#,
+            r#"
+            <a class="mdCMN38Item01Author" data-test="sticker-author" href="/stickershop/author/27290/en">Crypton Future Media</a>
+        "#,
         );
+        let actual = extract_author_from_document(&document);
+        assert_eq!(actual.unwrap(), "Crypton Future Media");
 
-        let sticker_data = extract_sticker_data_from_document(&document).unwrap();
+        let document = Html::parse_document(r#"<div></div>"#);
+        assert!(extract_author_from_document(&document).is_none());
+    }
 
-        assert_eq!(sticker_data.len(), 2);
+    #[test]
+    fn test_extract_description_from_document() {
+        let document = Html::parse_document(
+            r#"
+            <p class="mdCMN38Item01Txt" data-test="sticker-description">Miku stickers
+            for every day!</p>
+        "#,
+        );
+        let actual = extract_description_from_document(&document);
+        assert_eq!(actual.unwrap(), "Miku stickers\n            for every day!");
 
-        assert!(sticker_data.contains_key("20578528"));
-        let sticker_20578528 = sticker_data.get("20578528").unwrap();
+        let document = Html::parse_document(r#"<div></div>"#);
+        assert!(extract_description_from_document(&document).is_none());
+    }
+
+    #[test]
+    fn test_extract_preview_urls() {
+        let value: Value = serde_json::from_str(
+            r#"{ "type": "static", "id": "1", "staticUrl": "https://example.com/static.png", "animationUrl": "", "popupUrl": null }"#,
+        )
+        .unwrap();
+        let actual = extract_preview_urls(&value);
+        assert_eq!(actual.len(), 1);
+        assert_eq!(actual["staticUrl"], "https://example.com/static.png");
+    }
+
+    #[test]
+    fn test_extract_product_id_and_lang() {
+        let actual =
+            extract_product_id_and_lang("https://store.line.me/stickershop/product/28170905/en");
+        assert_eq!(actual, (Some("28170905"), Some("en")));
+
+        let actual = extract_product_id_and_lang(
+            "https://store.line.me/stickershop/product/683/zh-Hant?page=1",
+        );
+        assert_eq!(actual, (Some("683"), Some("zh-Hant")));
+
+        let actual = extract_product_id_and_lang("https://store.line.me/stickershop/product/683");
+        assert_eq!(actual, (Some("683"), None));
+
+        let actual = extract_product_id_and_lang("http://127.0.0.1:1234/test");
+        assert_eq!(actual, (None, None));
+    }
+
+    #[test]
+    fn test_extract_sticker_data_from_document() {
+        let document = Html::parse_document(
+            r#"<ul>
+                    <li class="for_testing"></li>
+                    <li class="mdCMN09Li FnStickerPreviewItem animation_sound-sticker " data-preview="{ &quot;type&quot; : &quot;animation_sound&quot;, &quot;id&quot; : &quot;20578528&quot;, &quot;staticUrl&quot; : &quot;https://stickershop.line-scdn.net/stickershop/v1/sticker/20578528/iPhone/sticker@2x.png?v=1&quot;, &quot;fallbackStaticUrl&quot; : &quot;https://stickershop.line-scdn.net/stickershop/v1/sticker/20578528/iPhone/sticker@2x.png?v=1&quot;, &quot;animationUrl&quot; : &quot;https://stickershop.line-scdn.net/stickershop/v1/sticker/20578528/iPhone/sticker_animation@2x.png?v=1&quot;, &quot;popupUrl&quot; : &quot;&quot;, &quot;soundUrl&quot; : &quot;https://stickershop.line-scdn.net/stickershop/v1/sticker/20578528/android/sticker_sound.m4a?v=1&quot; }" data-test="sticker-item"></li>
+                    <li class="for_testing" data-preview="{ &quot;type&quot; : &quot;animation&quot;, &quot;id&quot; : &quot;1&quot;}"></li>
+                    <li class="mdCMN09Li FnStickerPreviewItem animation-sticker " data-preview="{ &quot;type&quot; : &quot;animation&quot;, &quot;id&quot; : &quot;651763951&quot;, &quot;staticUrl&quot; : &quot;https://stickershop.line-scdn.net/stickershop/v1/sticker/651763951/iPhone/sticker@2x.png?v=2&quot;, &quot;fallbackStaticUrl&quot; : &quot;https://stickershop.line-scdn.net/stickershop/v1/sticker/651763951/iPhone/sticker@2x.png?v=2&quot;, &quot;animationUrl&quot; : &quot;https://stickershop.line-scdn.net/stickershop/v1/sticker/651763951/iPhone/sticker_animation@2x.png?v=2&quot;, &quot;popupUrl&quot; : &quot;&quot;, &quot;soundUrl&quot; : &quot;&quot; }" data-test="sticker-item">
+                    <li class="for_testing"></li>
+                </ul>"#,
+        );
+
+        let sticker_data = extract_sticker_data_from_document(&document).unwrap();
+
+        assert_eq!(sticker_data.len(), 2);
+
+        let sticker_20578528 = &sticker_data[0];
+        assert_eq!(sticker_20578528.index, 1);
+        assert_eq!(sticker_20578528.id, "20578528");
         assert_eq!(
-            sticker_20578528["animationUrl"].as_str().unwrap(),
+            sticker_20578528.preview["animationUrl"].as_str().unwrap(),
             "https://stickershop.line-scdn.net/stickershop/v1/sticker/20578528/iPhone/sticker_animation@2x.png?v=1"
         );
 
-        assert!(sticker_data.contains_key("651763951"));
-        let sticker_651763951 = sticker_data.get("651763951").unwrap();
+        let sticker_651763951 = &sticker_data[1];
+        assert_eq!(sticker_651763951.index, 2);
+        assert_eq!(sticker_651763951.id, "651763951");
         assert_eq!(
-            sticker_651763951["staticUrl"].as_str().unwrap(),
+            sticker_651763951.preview["staticUrl"].as_str().unwrap(),
             "https://stickershop.line-scdn.net/stickershop/v1/sticker/651763951/iPhone/sticker@2x.png?v=2"
         );
     }
 
+    #[test]
+    fn test_extract_sticker_data_from_document_keeps_store_order() {
+        let mut items = String::new();
+        for id in ["30", "10", "20", "10"] {
+            items.push_str(&format!(
+                r#"<li class="FnStickerPreviewItem" data-preview="{{ &quot;type&quot; : &quot;static&quot;, &quot;id&quot; : &quot;{id}&quot; }}"></li>"#
+            ));
+        }
+        let document = Html::parse_document(&format!("<ul>{}</ul>", items));
+
+        let sticker_data = extract_sticker_data_from_document(&document).unwrap();
+
+        let actual: Vec<(usize, &str)> = sticker_data
+            .iter()
+            .map(|sticker| (sticker.index, sticker.id.as_str()))
+            .collect();
+        assert_eq!(actual, vec![(1, "30"), (2, "10"), (3, "20")]);
+    }
+
     #[test]
     fn test_extract_sticker_data_from_document_error() {
         let document = Html::parse_document(
@@ -357,9 +1500,10 @@ mod tests {
     #[tokio::test]
     async fn test_download_file_error_no_extension() {
         let actual = download_file(
+            &Config::default(),
             "https://stickershop.line-scdn.net/stickershop/v1/sticker/20578528/android/sticker_sound",
             "test",
-            "test",
+            &Path::new("test").join("test"),
         ).await;
         assert!(actual.is_err());
     }
@@ -380,7 +1524,7 @@ mod tests {
             .create_async()
             .await;
 
-        download_stickers(format!("{}/test", url).as_str())
+        download_stickers(&Config::default(), format!("{}/test", url).as_str())
             .await
             .unwrap();
         let dir_path = std::path::Path::new("Pokémon Pixel Art Gold & Silver Edition");
@@ -388,10 +1532,10 @@ mod tests {
             dir_path.exists(),
             "Directory 'Pokémon Pixel Art Gold & Silver Edition' does not exist"
         );
-        let file_path = dir_path.join("20578551.m4a");
-        assert!(file_path.exists(), "File '20578551.m4a' does not exist");
-        let file_path = dir_path.join("20578551.png");
-        assert!(file_path.exists(), "File '20578551.png' does not exist");
+        let file_path = dir_path.join("01_20578551.m4a");
+        assert!(file_path.exists(), "File '01_20578551.m4a' does not exist");
+        let file_path = dir_path.join("01_20578551.png");
+        assert!(file_path.exists(), "File '01_20578551.png' does not exist");
 
         delete_directory_if_exists("Pokémon Pixel Art Gold & Silver Edition");
     }
@@ -413,14 +1557,16 @@ mod tests {
             .create_async()
             .await;
 
-        download_stickers(&format!("{}/test", url)).await.unwrap();
+        download_stickers(&Config::default(), &format!("{}/test", url))
+            .await
+            .unwrap();
         let dir_path = std::path::Path::new("THE POWERPUFF GIRLS X NEWJEANS");
         assert!(
             dir_path.exists(),
             "Directory 'THE POWERPUFF GIRLS X NEWJEANS' does not exist"
         );
-        let file_path = dir_path.join("616659318.png");
-        assert!(file_path.exists(), "File '616659318.png' does not exist");
+        let file_path = dir_path.join("01_616659318.png");
+        assert!(file_path.exists(), "File '01_616659318.png' does not exist");
 
         delete_directory_if_exists("THE POWERPUFF GIRLS X NEWJEANS");
     }
@@ -439,7 +1585,7 @@ mod tests {
             .create_async()
             .await;
 
-        let actual = download_stickers(&format!("{}/test", url)).await;
+        let actual = download_stickers(&Config::default(), &format!("{}/test", url)).await;
         assert!(actual.is_ok(), "{}", actual.unwrap_err());
     }
 
@@ -476,12 +1622,16 @@ mod tests {
             .create_async()
             .await;
 
-        let actual = download_stickers(&format!("{}/stickershop/author/test", url)).await;
+        let actual = download_stickers(
+            &Config::default(),
+            &format!("{}/stickershop/author/test", url),
+        )
+        .await;
         assert!(actual.is_err(), "{}", actual.unwrap_err());
     }
 
-    fn delete_directory_if_exists(directory: &str) {
-        let directory_path = std::path::Path::new(directory);
+    fn delete_directory_if_exists(directory: impl AsRef<Path>) {
+        let directory_path = directory.as_ref();
         if directory_path.exists() {
             std::fs::remove_dir_all(directory_path).expect("Failed to remove directory");
         }
@@ -579,7 +1729,7 @@ mod tests {
             product_url: String::from("/test"),
         }];
 
-        let actual = download_items(&url, items).await;
+        let actual = download_items(&Config::default(), &url, items).await;
         assert!(actual.is_ok(), "{}", actual.unwrap_err());
 
         let dir_path = std::path::Path::new("Test Download Items");
@@ -587,10 +1737,10 @@ mod tests {
             dir_path.exists(),
             "Directory 'Test Download Items' does not exist"
         );
-        let file_path = dir_path.join("20578551.m4a");
-        assert!(file_path.exists(), "File '20578551.m4a' does not exist");
-        let file_path = dir_path.join("20578551.png");
-        assert!(file_path.exists(), "File '20578551.png' does not exist");
+        let file_path = dir_path.join("01_20578551.m4a");
+        assert!(file_path.exists(), "File '01_20578551.m4a' does not exist");
+        let file_path = dir_path.join("01_20578551.png");
+        assert!(file_path.exists(), "File '01_20578551.png' does not exist");
 
         delete_directory_if_exists("Test Download Items");
     }
@@ -620,7 +1770,13 @@ mod tests {
             .create_async()
             .await;
 
-        let actual = download_stickers_from_search_query(&url, "hatsune+miku").await;
+        let actual = download_stickers_from_search_query(
+            &Config::default(),
+            &url,
+            "hatsune+miku",
+            Category::Sticker,
+        )
+        .await;
         assert!(actual.is_ok(), "{}", actual.unwrap_err());
 
         let dir_path = std::path::Path::new("Hatsune Miku");
@@ -634,107 +1790,1541 @@ mod tests {
     }
 
     #[tokio::test]
-    async fn test_process_args_missing_args() {
-        let args = vec!["program_name".to_string()];
-        let result = process_args("https://store.line.me", &args).await;
+    async fn test_build_client_sends_configured_headers() {
+        let mut server = mockito::Server::new_async().await;
+        let url = server.url();
 
-        assert!(result.is_err());
-        assert_eq!(
-            result.unwrap_err().to_string(),
-            "Usage: line-sticker-downloader <url1> <url2> ..."
+        let m = server
+            .mock("GET", "/test")
+            .match_header("user-agent", "Mozilla/5.0")
+            .match_header("cookie", "session=abc")
+            .match_header("x-custom", "value")
+            .with_status(200)
+            .with_header("content-type", "text/html;charset=UTF-8")
+            .with_body(r#"<p data-test="sticker-name-title">Test Client Headers</p>"#)
+            .create_async()
+            .await;
+
+        let options = HttpOptions {
+            user_agent: Some("Mozilla/5.0".to_string()),
+            headers: vec![
+                ("Cookie".to_string(), "session=abc".to_string()),
+                ("X-Custom".to_string(), "value".to_string()),
+            ],
+            ..HttpOptions::default()
+        };
+        let config = Config {
+            client: build_client(&options).unwrap(),
+            ..Config::default()
+        };
+
+        let actual = download_stickers(&config, &format!("{}/test", url)).await;
+        assert!(actual.is_ok(), "{}", actual.unwrap_err());
+        m.assert_async().await;
+    }
+
+    #[test]
+    fn test_parse_retry_after() {
+        let mut headers = reqwest::header::HeaderMap::new();
+        assert_eq!(parse_retry_after(&headers), None);
+
+        headers.insert(reqwest::header::RETRY_AFTER, "120".parse().unwrap());
+        assert_eq!(parse_retry_after(&headers), Some(Duration::from_secs(120)));
+
+        headers.insert(
+            reqwest::header::RETRY_AFTER,
+            "Wed, 21 Oct 2015 07:28:00 GMT".parse().unwrap(),
         );
+        assert_eq!(parse_retry_after(&headers), Some(Duration::ZERO));
+
+        let date = httpdate::fmt_http_date(std::time::SystemTime::now() + Duration::from_secs(60));
+        headers.insert(reqwest::header::RETRY_AFTER, date.parse().unwrap());
+        let actual = parse_retry_after(&headers).unwrap();
+        assert!(actual > Duration::from_secs(50) && actual <= Duration::from_secs(60));
+
+        headers.insert(reqwest::header::RETRY_AFTER, "soon".parse().unwrap());
+        assert_eq!(parse_retry_after(&headers), None);
+    }
+
+    #[test]
+    fn test_backoff_delay() {
+        for attempt in 1..=4 {
+            let max = RETRY_BASE_DELAY * 2u32.pow(attempt - 1);
+            let actual = backoff_delay(attempt);
+            assert!(actual >= max / 2 && actual <= max, "{:?}", actual);
+        }
     }
 
     #[tokio::test]
-    async fn test_process_args_with_url() {
+    async fn test_fetch_retries_transient_errors() {
         let mut server = mockito::Server::new_async().await;
-        let base_url = server.url();
+        let url = server.url();
 
-        let _m = server
-            .mock("GET", "/test")
+        let m = server
+            .mock("GET", "/sticker.png")
+            .with_status(503)
+            .expect(1)
+            .create_async()
+            .await;
+        let m2 = server
+            .mock("GET", "/sticker.png")
+            .with_status(429)
+            .with_header("retry-after", "0")
+            .expect(1)
+            .create_async()
+            .await;
+        let m3 = server
+            .mock("GET", "/sticker.png")
             .with_status(200)
-            .with_header("content-type", "text/html;charset=UTF-8")
-            .with_body(
-                r#"
-                <p class="mdCMN38Item01Ttl" data-test="sticker-name-title">Test Stickers</p>
-            "#,
-            )
+            .with_body(PNG_BYTES)
             .create_async()
             .await;
 
-        let args = vec!["program_name".to_string(), format!("{}/test", base_url)];
+        let config = Config {
+            retry_max_delay: Duration::from_millis(1),
+            ..Config::default()
+        };
+        let actual = fetch(&config, &format!("{}/sticker.png", url))
+            .await
+            .unwrap();
+        assert_eq!(actual.status, reqwest::StatusCode::OK);
+        assert_eq!(actual.body, PNG_BYTES);
+
+        m.assert_async().await;
+        m2.assert_async().await;
+        m3.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_fetch_does_not_retry_client_errors() {
+        let mut server = mockito::Server::new_async().await;
+        let url = server.url();
 
-        let result = process_args(&base_url, &args).await;
-        assert!(result.is_ok(), "Failed to process args: {:?}", result.err());
+        let m = server
+            .mock("GET", "/sticker.png")
+            .with_status(404)
+            .expect(1)
+            .create_async()
+            .await;
 
-        let dir_path = std::path::Path::new("Test Stickers");
+        let actual = fetch(&Config::default(), &format!("{}/sticker.png", url))
+            .await
+            .unwrap();
+        assert_eq!(actual.status, reqwest::StatusCode::NOT_FOUND);
+        m.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_download_file_gives_up_after_retries() {
+        let mut server = mockito::Server::new_async().await;
+        let url = server.url();
+
+        let m = server
+            .mock("GET", "/sticker.png")
+            .with_status(500)
+            .expect(3)
+            .create_async()
+            .await;
+
+        let config = Config {
+            retries: 2,
+            retry_max_delay: Duration::from_millis(1),
+            ..Config::default()
+        };
+        let actual = download_file(
+            &config,
+            &format!("{}/sticker.png", url),
+            "12345",
+            &Path::new("Test Download File Gives Up").join("12345.png"),
+        )
+        .await;
+
+        let err = actual.unwrap_err().to_string();
         assert!(
-            !dir_path.exists(),
-            "Directory 'Test Stickers' should not exist because no download happened."
+            err.starts_with("Failed to download sticker 12345: Giving up on"),
+            "{}",
+            err
+        );
+        assert!(err.ends_with("after 3 attempts: HTTP status 500 Internal Server Error"));
+        m.assert_async().await;
+
+        delete_directory_if_exists("Test Download File Gives Up");
+    }
+
+    #[test]
+    fn test_validate_file_contents() {
+        let response = |content_type: &str, body: &[u8]| {
+            let mut headers = reqwest::header::HeaderMap::new();
+            headers.insert(reqwest::header::CONTENT_TYPE, content_type.parse().unwrap());
+            FetchResponse {
+                status: reqwest::StatusCode::OK,
+                headers,
+                body: body.to_vec(),
+            }
+        };
+
+        assert!(validate_file_contents("png", &response("image/png", PNG_BYTES)).is_ok());
+        assert!(validate_file_contents("m4a", &response("audio/mp4", M4A_BYTES)).is_ok());
+        assert!(validate_file_contents("m4a", &response("audio/mp4", PNG_BYTES)).is_err());
+        assert!(validate_file_contents("png", &response("image/png", M4A_BYTES)).is_err());
+        assert!(validate_file_contents("png", &response("image/png", b"")).is_err());
+
+        let actual = validate_file_contents("png", &response("text/html", PNG_BYTES));
+        assert_eq!(
+            actual.unwrap_err().to_string(),
+            "Unexpected content type text/html for a .png file"
         );
     }
 
     #[tokio::test]
-    async fn test_process_args_with_invalid_url() {
+    async fn test_download_file_error_status() {
         let mut server = mockito::Server::new_async().await;
-        let base_url = server.url();
+        let url = server.url();
 
         let _m = server
-            .mock("GET", "/test")
-            .with_status(200)
+            .mock("GET", "/sticker.png")
+            .with_status(404)
             .with_header("content-type", "text/html;charset=UTF-8")
-            .with_body(
-                r#"
-                <div></div>
-            "#,
-            )
+            .with_body("<html>Not Found</html>")
             .create_async()
             .await;
 
-        let args = vec!["program_name".to_string(), format!("{}/test", base_url)];
-
-        let result = process_args(&base_url, &args).await;
-        assert!(result.is_err());
+        let actual = download_file(
+            &Config::default(),
+            &format!("{}/sticker.png", url),
+            "12345",
+            &Path::new("Test Download File Error Status").join("12345.png"),
+        )
+        .await;
         assert_eq!(
-            result.unwrap_err().to_string(),
-            "Failed to fetch stickers: Could not find the sticker-name-title in the document. Please check that the URL points to a valid sticker page."
+            actual.unwrap_err().to_string(),
+            format!(
+                "Failed to download sticker 12345: {}/sticker.png responded with HTTP status 404 Not Found",
+                url
+            )
         );
+
+        let file_path = std::path::Path::new("Test Download File Error Status").join("12345.png");
+        assert!(!file_path.exists(), "File '12345.png' should not exist");
+
+        delete_directory_if_exists("Test Download File Error Status");
     }
 
     #[tokio::test]
-    async fn test_process_args_with_search_query() {
+    async fn test_download_file_invalid_contents() {
         let mut server = mockito::Server::new_async().await;
-        let base_url = server.url();
+        let url = server.url();
 
         let _m = server
-            .mock("GET", "/api/search/sticker?category=sticker&type=ALL&offset=0&limit=36&includeFacets=false&query=test")
+            .mock("GET", "/sticker_sound.m4a")
             .with_status(200)
-            .with_header("content-type", "application/json")
-            .with_body(r#"{"totalCount":1,"items":[{"productUrl": "/test"}]}"#)
+            .with_body("<html>Maintenance</html>")
             .create_async()
             .await;
 
-        let _m2 = server
+        let actual = download_file(
+            &Config::default(),
+            &format!("{}/sticker_sound.m4a", url),
+            "12345",
+            &Path::new("Test Download File Invalid Contents").join("12345.m4a"),
+        )
+        .await;
+        assert_eq!(
+            actual.unwrap_err().to_string(),
+            "Failed to download sticker 12345: Downloaded content is not a valid .m4a file"
+        );
+
+        let file_path =
+            std::path::Path::new("Test Download File Invalid Contents").join("12345.m4a");
+        assert!(!file_path.exists(), "File '12345.m4a' should not exist");
+
+        delete_directory_if_exists("Test Download File Invalid Contents");
+    }
+
+    #[tokio::test]
+    async fn test_download_stickers_error_status() {
+        let mut server = mockito::Server::new_async().await;
+        let url = server.url();
+
+        let _m = server
             .mock("GET", "/test")
-            .with_status(200)
+            .with_status(404)
             .with_header("content-type", "text/html;charset=UTF-8")
-            .with_body(
-                r#"
-                <p class="mdCMN38Item01Ttl" data-test="sticker-name-title">Test Search Results</p>
-            "#,
-            )
+            .with_body(r#"<p data-test="sticker-name-title">Not Found</p>"#)
             .create_async()
             .await;
 
-        let args = vec!["program_name".to_string(), "test".to_string()];
+        let actual = download_stickers(&Config::default(), &format!("{}/test", url)).await;
+        assert_eq!(
+            actual.unwrap_err().to_string(),
+            format!("{}/test responded with HTTP status 404 Not Found", url)
+        );
+    }
 
-        let result = process_args(&base_url, &args).await;
-        assert!(result.is_ok(), "Failed to process args: {:?}", result.err());
+    #[tokio::test]
+    async fn test_download_file_skips_up_to_date_file() {
+        let mut server = mockito::Server::new_async().await;
+        let url = server.url();
 
-        let dir_path = std::path::Path::new("Test Search Results");
+        let head = server
+            .mock("HEAD", "/sticker.png")
+            .with_status(200)
+            .with_header("content-length", &PNG_BYTES.len().to_string())
+            .with_header("last-modified", "Wed, 21 Oct 2015 07:28:00 GMT")
+            .expect(1)
+            .create_async()
+            .await;
+        let get = server
+            .mock("GET", "/sticker.png")
+            .with_status(200)
+            .with_body(PNG_BYTES)
+            .expect(0)
+            .create_async()
+            .await;
+
+        let directory = Path::new("Test Download File Skips Up To Date File");
+        std::fs::create_dir_all(directory).unwrap();
+        std::fs::write(directory.join("12345.png"), PNG_BYTES).unwrap();
+
+        let actual = download_file(
+            &Config::default(),
+            &format!("{}/sticker.png", url),
+            "12345",
+            &directory.join("12345.png"),
+        )
+        .await;
+        assert!(actual.is_ok(), "{}", actual.unwrap_err());
+        head.assert_async().await;
+        get.assert_async().await;
+
+        delete_directory_if_exists(directory);
+    }
+
+    #[tokio::test]
+    async fn test_download_file_skips_not_modified_file() {
+        let mut server = mockito::Server::new_async().await;
+        let url = server.url();
+
+        let head = server
+            .mock("HEAD", "/sticker.png")
+            .match_header("if-modified-since", mockito::Matcher::Any)
+            .with_status(304)
+            .expect(1)
+            .create_async()
+            .await;
+        let get = server
+            .mock("GET", "/sticker.png")
+            .with_status(200)
+            .with_body(PNG_BYTES)
+            .expect(0)
+            .create_async()
+            .await;
+
+        let directory = Path::new("Test Download File Skips Not Modified File");
+        std::fs::create_dir_all(directory).unwrap();
+        std::fs::write(directory.join("12345.png"), PNG_BYTES).unwrap();
+
+        let actual = download_file(
+            &Config::default(),
+            &format!("{}/sticker.png", url),
+            "12345",
+            &directory.join("12345.png"),
+        )
+        .await;
+        assert!(actual.is_ok(), "{}", actual.unwrap_err());
+        head.assert_async().await;
+        get.assert_async().await;
+
+        delete_directory_if_exists(directory);
+    }
+
+    #[test]
+    fn test_matches_validators() {
+        use reqwest::header::{HeaderMap, HeaderValue, CONTENT_LENGTH, LAST_MODIFIED};
+
+        let modified = httpdate::parse_http_date("Wed, 21 Oct 2015 07:28:00 GMT").unwrap();
+        let headers = |content_length: Option<&'static str>,
+                       last_modified: Option<&'static str>| {
+            let mut headers = HeaderMap::new();
+            if let Some(content_length) = content_length {
+                headers.insert(CONTENT_LENGTH, HeaderValue::from_static(content_length));
+            }
+            if let Some(last_modified) = last_modified {
+                headers.insert(LAST_MODIFIED, HeaderValue::from_static(last_modified));
+            }
+            headers
+        };
+
+        assert!(!matches_validators(&headers(None, None), 4, modified));
+        assert!(matches_validators(&headers(Some("4"), None), 4, modified));
+        assert!(!matches_validators(&headers(Some("5"), None), 4, modified));
+        let older = Some("Tue, 20 Oct 2015 07:28:00 GMT");
+        let newer = Some("Thu, 22 Oct 2015 07:28:00 GMT");
+        assert!(matches_validators(&headers(None, older), 4, modified));
+        assert!(!matches_validators(&headers(None, newer), 4, modified));
+        assert!(!matches_validators(&headers(Some("4"), newer), 4, modified));
+        assert!(!matches_validators(
+            &headers(None, Some("yesterday")),
+            4,
+            modified
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_download_file_replaces_incomplete_file() {
+        let mut server = mockito::Server::new_async().await;
+        let url = server.url();
+
+        let _head = server
+            .mock("HEAD", "/sticker.png")
+            .with_status(200)
+            .with_header("content-length", &PNG_BYTES.len().to_string())
+            .create_async()
+            .await;
+        let get = server
+            .mock("GET", "/sticker.png")
+            .with_status(200)
+            .with_body(PNG_BYTES)
+            .expect(1)
+            .create_async()
+            .await;
+
+        let directory = Path::new("Test Download File Replaces Incomplete File");
+        std::fs::create_dir_all(directory).unwrap();
+        std::fs::write(directory.join("12345.png"), &PNG_BYTES[..4]).unwrap();
+
+        let actual = download_file(
+            &Config::default(),
+            &format!("{}/sticker.png", url),
+            "12345",
+            &directory.join("12345.png"),
+        )
+        .await;
+        assert!(actual.is_ok(), "{}", actual.unwrap_err());
+        get.assert_async().await;
+
+        let file_path = directory.join("12345.png");
+        assert_eq!(std::fs::read(&file_path).unwrap(), PNG_BYTES);
+        let temp_path = directory.join("12345.png.part");
         assert!(
-            !dir_path.exists(),
-            "Directory 'Test Search Results' should not exist because no download happened."
+            !temp_path.exists(),
+            "File '12345.png.part' should not exist"
+        );
+
+        delete_directory_if_exists(directory);
+    }
+
+    #[tokio::test]
+    async fn test_download_file_force() {
+        let mut server = mockito::Server::new_async().await;
+        let url = server.url();
+
+        let head = server
+            .mock("HEAD", "/sticker.png")
+            .with_status(200)
+            .expect(0)
+            .create_async()
+            .await;
+        let get = server
+            .mock("GET", "/sticker.png")
+            .with_status(200)
+            .with_body(PNG_BYTES)
+            .expect(1)
+            .create_async()
+            .await;
+
+        let directory = Path::new("Test Download File Force");
+        std::fs::create_dir_all(directory).unwrap();
+        std::fs::write(directory.join("12345.png"), PNG_BYTES).unwrap();
+
+        let config = Config {
+            force: true,
+            ..Config::default()
+        };
+        let actual = download_file(
+            &config,
+            &format!("{}/sticker.png", url),
+            "12345",
+            &directory.join("12345.png"),
+        )
+        .await;
+        assert!(actual.is_ok(), "{}", actual.unwrap_err());
+        head.assert_async().await;
+        get.assert_async().await;
+
+        delete_directory_if_exists(directory);
+    }
+
+    #[test]
+    fn test_extract_sticker_urls() {
+        let all = [Asset::Image, Asset::Sound, Asset::Popup];
+        let value: Value = serde_json::from_str(
+            r#"{ "id": "1", "staticUrl": "https://example.com/static.png", "animationUrl": "https://example.com/animation.png", "soundUrl": "https://example.com/sound.m4a", "popupUrl": "" }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            extract_sticker_urls(&value, &all),
+            vec![
+                (Asset::Sound, "https://example.com/sound.m4a"),
+                (Asset::Image, "https://example.com/animation.png")
+            ]
         );
+
+        let value: Value = serde_json::from_str(
+            r#"{ "id": "1", "staticUrl": "https://example.com/static.png", "animationUrl": "", "soundUrl": "" }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            extract_sticker_urls(&value, &all),
+            vec![(Asset::Image, "https://example.com/static.png")]
+        );
+
+        let value: Value = serde_json::from_str(
+            r#"{ "id": "1", "staticUrl": "https://example.com/static.png", "animationUrl": "", "soundUrl": "", "popupUrl": "https://example.com/popup.png" }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            extract_sticker_urls(&value, &all),
+            vec![
+                (Asset::Image, "https://example.com/static.png"),
+                (Asset::Popup, "https://example.com/popup.png")
+            ]
+        );
+        assert_eq!(
+            extract_sticker_urls(&value, &[Asset::Popup]),
+            vec![(Asset::Popup, "https://example.com/popup.png")]
+        );
+
+        let value: Value = serde_json::from_str(
+            r#"{ "id": "1", "staticUrl": "https://example.com/static.png", "fallbackStaticUrl": "https://example.com/fallback.png", "animationUrl": "https://example.com/animation.png", "soundUrl": "https://example.com/sound.m4a", "popupUrl": "" }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            extract_sticker_urls(&value, &Asset::ALL_VARIANTS),
+            vec![
+                (Asset::Sound, "https://example.com/sound.m4a"),
+                (Asset::Static, "https://example.com/static.png"),
+                (Asset::Animation, "https://example.com/animation.png"),
+                (Asset::FallbackStatic, "https://example.com/fallback.png"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_ensure_unique_paths() {
+        let paths = [PathBuf::from("a/1.png"), PathBuf::from("a/1_popup.png")];
+        assert!(ensure_unique_paths(paths.iter()).is_ok());
+
+        let paths = [PathBuf::from("a/1.png"), PathBuf::from("a/1.png")];
+        assert!(ensure_unique_paths(paths.iter()).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_download_stickers_concurrently() {
+        let mut server = mockito::Server::new_async().await;
+        let url = server.url();
+
+        let mut items = String::new();
+        for id in 1..=6 {
+            items.push_str(&format!(
+                r#"<li class="FnStickerPreviewItem" data-preview="{{ &quot;type&quot; : &quot;static&quot;, &quot;id&quot; : &quot;{id}&quot;, &quot;staticUrl&quot; : &quot;{url}/sticker/{id}.png&quot;, &quot;animationUrl&quot; : &quot;&quot;, &quot;soundUrl&quot; : &quot;&quot; }}"></li>"#
+            ));
+        }
+
+        let _m = server
+            .mock("GET", "/test")
+            .with_status(200)
+            .with_header("content-type", "text/html;charset=UTF-8")
+            .with_body(format!(
+                r#"<p data-test="sticker-name-title">Test Concurrent Downloads</p><ul>{}</ul>"#,
+                items
+            ))
+            .create_async()
+            .await;
+        let m2 = server
+            .mock(
+                "GET",
+                mockito::Matcher::Regex(r"^/sticker/\d\.png$".to_string()),
+            )
+            .with_status(200)
+            .with_body(PNG_BYTES)
+            .expect(6)
+            .create_async()
+            .await;
+
+        let config = Config {
+            jobs: 3,
+            ..Config::default()
+        };
+        let actual = download_stickers(&config, &format!("{}/test", url)).await;
+        assert!(actual.is_ok(), "{}", actual.unwrap_err());
+        m2.assert_async().await;
+
+        let dir_path = std::path::Path::new("Test Concurrent Downloads");
+        for id in 1..=6 {
+            let file_path = dir_path.join(format!("{:02}_{}.png", id, id));
+            assert!(
+                file_path.exists(),
+                "File '{:02}_{}.png' does not exist",
+                id,
+                id
+            );
+        }
+
+        delete_directory_if_exists("Test Concurrent Downloads");
+    }
+
+    #[tokio::test]
+    async fn test_download_stickers_concurrently_fails_pack_on_error() {
+        let mut server = mockito::Server::new_async().await;
+        let url = server.url();
+
+        let _m = server
+            .mock("GET", "/test")
+            .with_status(200)
+            .with_header("content-type", "text/html;charset=UTF-8")
+            .with_body(format!(
+                r#"<p data-test="sticker-name-title">Test Concurrent Downloads Error</p>
+                <ul>
+                    <li class="FnStickerPreviewItem" data-preview="{{ &quot;id&quot; : &quot;1&quot;, &quot;staticUrl&quot; : &quot;{url}/sticker/1.png&quot; }}"></li>
+                    <li class="FnStickerPreviewItem" data-preview="{{ &quot;id&quot; : &quot;2&quot;, &quot;staticUrl&quot; : &quot;{url}/sticker/2&quot; }}"></li>
+                </ul>"#
+            ))
+            .create_async()
+            .await;
+        let _m2 = server
+            .mock("GET", "/sticker/1.png")
+            .with_status(200)
+            .with_body(PNG_BYTES)
+            .create_async()
+            .await;
+
+        let actual = download_stickers(&Config::default(), &format!("{}/test", url)).await;
+        assert!(actual.is_err());
+
+        delete_directory_if_exists("Test Concurrent Downloads Error");
+    }
+
+    #[test]
+    fn test_product_url() {
+        let expected = "https://store.line.me/stickershop/product/6920150/en";
+        for input in [
+            "6920150",
+            " 6920150 ",
+            "line://shop/detail/6920150",
+            "line://shop/sticker/detail/6920150",
+            "https://line.me/S/sticker/6920150",
+            "https://line.me/S/sticker/6920150/?ref=gnsh_stickerDetail",
+            "https://line.me/R/shop/detail/6920150",
+            "line.me/S/sticker/6920150",
+            "https://store.line.me/stickershop/product/6920150/en",
+            "https://store.line.me/stickershop/product/6920150",
+            "https://store.line.me/stickershop/product/6920150/",
+            "https://store.line.me/stickershop/product/6920150/en?from=sticker",
+            "store.line.me/stickershop/product/6920150",
+            "http://m.store.line.me/stickershop/product/6920150/en",
+        ] {
+            let actual = product_url("https://store.line.me", "en", input).unwrap();
+            assert_eq!(actual, expected, "{}", input);
+        }
+
+        let expected = "https://store.line.me/stickershop/product/683/ja";
+        for input in [
+            "store.line.me/stickershop/product/683/ja",
+            "https://store.line.me/stickershop/product/683/ja#stickers",
+            "https://line.me/S/sticker/683/?lang=ja&ref=gnsh_stickerDetail",
+            "https://store.line.me/stickershop/product/683?lang=ja",
+        ] {
+            let actual = product_url("https://store.line.me", "en", input).unwrap();
+            assert_eq!(actual, expected, "{}", input);
+        }
+
+        let actual = product_url("https://store.line.me", "zh-Hant", "683").unwrap();
+        assert_eq!(
+            actual,
+            "https://store.line.me/stickershop/product/683/zh-Hant"
+        );
+
+        let actual = product_url("https://store.line.me", "en", "http://127.0.0.1:1234/test");
+        assert_eq!(actual.unwrap(), "http://127.0.0.1:1234/test");
+
+        let expected = "https://store.line.me/emojishop/product/5ac1bfd5040ab15980c9b435/en";
+        for input in [
+            "5ac1bfd5040ab15980c9b435",
+            "line://shop/emoji/detail/5ac1bfd5040ab15980c9b435",
+            "https://line.me/S/emoji/?id=5ac1bfd5040ab15980c9b435",
+            "https://line.me/S/emoji?id=5ac1bfd5040ab15980c9b435&ref=gnsh_emojiDetail",
+            "https://store.line.me/emojishop/product/5ac1bfd5040ab15980c9b435/en",
+            "store.line.me/emojishop/product/5ac1bfd5040ab15980c9b435",
+        ] {
+            let actual = product_url("https://store.line.me", "en", input).unwrap();
+            assert_eq!(actual, expected, "{}", input);
+        }
+
+        let expected =
+            "https://store.line.me/themeshop/product/a0768339-c2d3-4189-9653-2909e9bb6f58/ja";
+        for input in [
+            "a0768339-c2d3-4189-9653-2909e9bb6f58",
+            "https://store.line.me/themeshop/product/a0768339-c2d3-4189-9653-2909e9bb6f58/ja",
+        ] {
+            let actual = product_url("https://store.line.me", "ja", input).unwrap();
+            assert_eq!(actual, expected, "{}", input);
+        }
+
+        assert!(product_url("https://store.line.me", "en", "hatsune miku").is_err());
+        assert!(product_url("https://store.line.me", "en", "").is_err());
+    }
+
+    #[test]
+    fn test_author_url() {
+        let actual = author_url("https://store.line.me", "ja", "27290").unwrap();
+        assert_eq!(actual, "https://store.line.me/stickershop/author/27290/ja");
+
+        assert!(author_url("https://store.line.me", "en", "author").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_run_download_with_id() {
+        let mut server = mockito::Server::new_async().await;
+        let base_url = server.url();
+
+        let m = server
+            .mock("GET", "/stickershop/product/6920150/ja")
+            .with_status(200)
+            .with_header("content-type", "text/html;charset=UTF-8")
+            .with_body(r#"<p data-test="sticker-name-title">Test Download With Id</p>"#)
+            .create_async()
+            .await;
+
+        let _m2 = server
+            .mock(
+                "GET",
+                "/stickershop/v1/product/6920150/android/productInfo.meta",
+            )
+            .with_status(404)
+            .create_async()
+            .await;
+
+        let cli =
+            Cli::try_parse_from(["program_name", "download", "6920150", "--lang", "ja"]).unwrap();
+        let result = run(&base_url, &base_url, cli).await;
+        assert!(result.is_ok(), "Failed to run: {:?}", result.err());
+        m.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_run_author_with_id() {
+        let mut server = mockito::Server::new_async().await;
+        let base_url = server.url();
+
+        let m = server
+            .mock("GET", "/stickershop/author/27290/en")
+            .with_status(200)
+            .with_header("content-type", "text/html;charset=UTF-8")
+            .with_body("<ul></ul>")
+            .create_async()
+            .await;
+
+        let cli = Cli::try_parse_from(["program_name", "author", "27290"]).unwrap();
+        let result = run(&base_url, &base_url, cli).await;
+        assert!(result.is_ok(), "Failed to run: {:?}", result.err());
+        m.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_run_info() {
+        let mut server = mockito::Server::new_async().await;
+        let base_url = server.url();
+
+        let _m = server
+            .mock("GET", "/stickershop/product/1/en")
+            .with_status(200)
+            .with_header("content-type", "text/html;charset=UTF-8")
+            .with_body(format!(
+                r#"<p data-test="sticker-name-title">Test Info</p>
+                <ul>
+                    <li class="FnStickerPreviewItem" data-preview="{{ &quot;type&quot; : &quot;static&quot;, &quot;id&quot; : &quot;1&quot;, &quot;staticUrl&quot; : &quot;{base_url}/sticker/1.png&quot; }}"></li>
+                </ul>"#
+            ))
+            .create_async()
+            .await;
+        let m2 = server
+            .mock("GET", "/sticker/1.png")
+            .expect(0)
+            .create_async()
+            .await;
+
+        let cli = Cli::try_parse_from(["program_name", "info", "1", "-v"]).unwrap();
+        let result = run(&base_url, &base_url, cli).await;
+        assert!(result.is_ok(), "Failed to run: {:?}", result.err());
+        m2.assert_async().await;
+        assert!(!Path::new("Test Info").exists());
+    }
+
+    #[tokio::test]
+    async fn test_download_stickers_output_and_dry_run() {
+        let mut server = mockito::Server::new_async().await;
+        let url = server.url();
+
+        let _m = server
+            .mock("GET", "/test")
+            .with_status(200)
+            .with_header("content-type", "text/html;charset=UTF-8")
+            .with_body(format!(
+                r#"<p data-test="sticker-name-title">Test Output</p>
+                <ul>
+                    <li class="FnStickerPreviewItem" data-preview="{{ &quot;type&quot; : &quot;static&quot;, &quot;id&quot; : &quot;1&quot;, &quot;staticUrl&quot; : &quot;{url}/sticker/1.png&quot; }}"></li>
+                </ul>"#
+            ))
+            .create_async()
+            .await;
+        let m2 = server
+            .mock("GET", "/sticker/1.png")
+            .with_status(200)
+            .with_body(PNG_BYTES)
+            .expect(1)
+            .create_async()
+            .await;
+
+        let output = Path::new("Test Output Directory");
+        let config = Config {
+            output: output.to_path_buf(),
+            dry_run: true,
+            ..Config::default()
+        };
+        let actual = download_stickers(&config, &format!("{}/test", url)).await;
+        assert!(actual.is_ok(), "{}", actual.unwrap_err());
+        assert!(!output.exists(), "Dry run should not create any directory");
+
+        let config = Config {
+            dry_run: false,
+            ..config
+        };
+        let actual = download_stickers(&config, &format!("{}/test", url)).await;
+        assert!(actual.is_ok(), "{}", actual.unwrap_err());
+        m2.assert_async().await;
+        let file_path = output.join("Test Output").join("01_1.png");
+        assert!(file_path.exists(), "File '01_1.png' does not exist");
+
+        delete_directory_if_exists(output);
+    }
+
+    #[tokio::test]
+    async fn test_download_stickers_template() {
+        let mut server = mockito::Server::new_async().await;
+        let url = server.url();
+
+        for (id, author) in [("1", "Author A"), ("2", "Author B")] {
+            server
+                .mock("GET", format!("/stickershop/product/{}/ja", id).as_str())
+                .with_status(200)
+                .with_header("content-type", "text/html;charset=UTF-8")
+                .with_body(format!(
+                    r#"<p data-test="sticker-name-title">Same Title</p>
+                    <a data-test="sticker-author">{author}</a>
+                    <ul>
+                        <li class="FnStickerPreviewItem" data-preview="{{ &quot;type&quot; : &quot;static&quot;, &quot;id&quot; : &quot;10{id}&quot;, &quot;staticUrl&quot; : &quot;{url}/sticker/10{id}.png&quot; }}"></li>
+                    </ul>"#
+                ))
+                .create_async()
+                .await;
+        }
+        let _m = server
+            .mock(
+                "GET",
+                mockito::Matcher::Regex(r"^/sticker/\d+\.png$".to_string()),
+            )
+            .with_status(200)
+            .with_body(PNG_BYTES)
+            .create_async()
+            .await;
+
+        let _m2 = server
+            .mock(
+                "GET",
+                mockito::Matcher::Regex(r"^/stickershop/v1/".to_string()),
+            )
+            .with_status(404)
+            .create_async()
+            .await;
+
+        let output = Path::new("Test Template Output");
+        let config = Config {
+            output: output.to_path_buf(),
+            cdn_url: url.clone(),
+            template: PathTemplate::parse(
+                "{author}/{pack_id} - {title} ({lang})/{index:02}_{sticker_id}_{type}.{ext}",
+            )
+            .unwrap(),
+            ..Config::default()
+        };
+        for id in ["1", "2"] {
+            let actual =
+                download_stickers(&config, &format!("{}/stickershop/product/{}/ja", url, id)).await;
+            assert!(actual.is_ok(), "{}", actual.unwrap_err());
+        }
+
+        let file_path = output
+            .join("Author A")
+            .join("1 - Same Title (ja)")
+            .join("01_101_static.png");
+        assert!(
+            file_path.exists(),
+            "File '{}' does not exist",
+            file_path.display()
+        );
+        let file_path = output
+            .join("Author B")
+            .join("2 - Same Title (ja)")
+            .join("01_102_static.png");
+        assert!(
+            file_path.exists(),
+            "File '{}' does not exist",
+            file_path.display()
+        );
+
+        delete_directory_if_exists(output);
+    }
+
+    #[tokio::test]
+    async fn test_download_stickers_writes_manifest() {
+        let mut server = mockito::Server::new_async().await;
+        let url = server.url();
+
+        let _m = server
+            .mock("GET", "/stickershop/product/1/ja")
+            .with_status(200)
+            .with_header("content-type", "text/html;charset=UTF-8")
+            .with_body(format!(
+                r#"<p data-test="sticker-name-title">Test Manifest HTML</p>
+                <a data-test="sticker-author">HTML Author</a>
+                <p data-test="sticker-description">Description</p>
+                <ul>
+                    <li class="FnStickerPreviewItem" data-preview="{{ &quot;type&quot; : &quot;animation_sound&quot;, &quot;id&quot; : &quot;10&quot;, &quot;staticUrl&quot; : &quot;{url}/sticker/10/static.png&quot;, &quot;animationUrl&quot; : &quot;{url}/sticker/10/animation.png&quot;, &quot;popupUrl&quot; : &quot;&quot;, &quot;soundUrl&quot; : &quot;{url}/sticker/10/sound.m4a&quot; }}"></li>
+                </ul>"#
+            ))
+            .create_async()
+            .await;
+        let _m2 = server
+            .mock("GET", "/sticker/10/animation.png")
+            .with_status(200)
+            .with_body(PNG_BYTES)
+            .create_async()
+            .await;
+        let _m3 = server
+            .mock("GET", "/sticker/10/sound.m4a")
+            .with_status(200)
+            .with_body(M4A_BYTES)
+            .create_async()
+            .await;
+        let _m4 = server
+            .mock("GET", "/stickershop/v1/product/1/android/productInfo.meta")
+            .with_status(200)
+            .with_body(r#"{"packageId":1,"title":{"en":"Test Manifest EN","ja":"Test Manifest"},"author":{"en":"Author"},"stickers":[{"id":10,"width":320,"height":296}],"stickerResourceType":"ANIMATION_SOUND"}"#)
+            .create_async()
+            .await;
+
+        let output = Path::new("Test Manifest Output");
+        let config = Config {
+            output: output.to_path_buf(),
+            cdn_url: url.clone(),
+            ..Config::default()
+        };
+        let source_url = format!("{}/stickershop/product/1/ja", url);
+        let actual = download_stickers(&config, &source_url).await;
+        assert!(actual.is_ok(), "{}", actual.unwrap_err());
+
+        let manifest_path = output.join("Test Manifest").join("pack.json");
+        let manifest: Value =
+            serde_json::from_slice(&std::fs::read(&manifest_path).unwrap()).unwrap();
+        delete_directory_if_exists(output);
+
+        assert_eq!(manifest["pack_id"], "1");
+        assert_eq!(manifest["title"], "Test Manifest");
+        assert_eq!(manifest["author"], "Author");
+        assert_eq!(manifest["description"], "Description");
+        assert_eq!(manifest["lang"], "ja");
+        assert_eq!(manifest["source_url"], source_url.as_str());
+        assert!(manifest["downloaded_at"].as_str().unwrap().ends_with('Z'));
+
+        let sticker = &manifest["stickers"][0];
+        assert_eq!(sticker["id"], "10");
+        assert_eq!(sticker["order"], 1);
+        assert_eq!(sticker["type"], "animation_sound");
+        assert_eq!(sticker["width"], 320);
+        assert_eq!(sticker["height"], 296);
+        assert_eq!(
+            sticker["urls"]["staticUrl"],
+            format!("{}/sticker/10/static.png", url).as_str()
+        );
+        assert!(sticker["urls"].get("popupUrl").is_none());
+
+        let files = sticker["files"].as_array().unwrap();
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0]["file_name"], "01_10.m4a");
+        assert_eq!(files[0]["asset"], "sound");
+        assert_eq!(files[0]["size"], M4A_BYTES.len());
+        assert_eq!(files[1]["file_name"], "01_10.png");
+        assert_eq!(files[1]["asset"], "image");
+        assert_eq!(files[1]["size"], PNG_BYTES.len());
+        assert_eq!(files[1]["sha256"].as_str().unwrap().len(), 64);
+    }
+
+    #[tokio::test]
+    async fn test_download_stickers_convert_gif() {
+        let mut server = mockito::Server::new_async().await;
+        let url = server.url();
+
+        let mut apng = Vec::new();
+        let mut encoder = png::Encoder::new(&mut apng, 1, 1);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_animated(2, 0).unwrap();
+        let mut writer = encoder.write_header().unwrap();
+        writer.write_image_data(&[255, 0, 0, 255]).unwrap();
+        writer.write_image_data(&[0, 0, 255, 255]).unwrap();
+        writer.finish().unwrap();
+
+        let _m = server
+            .mock("GET", "/stickershop/product/1/en")
+            .with_status(200)
+            .with_header("content-type", "text/html;charset=UTF-8")
+            .with_body(format!(
+                r#"<p data-test="sticker-name-title">Test Convert GIF</p>
+                <ul>
+                    <li class="FnStickerPreviewItem" data-preview="{{ &quot;type&quot; : &quot;animation&quot;, &quot;id&quot; : &quot;10&quot;, &quot;staticUrl&quot; : &quot;{url}/sticker/10/static.png&quot;, &quot;animationUrl&quot; : &quot;{url}/sticker/10/animation.png&quot; }}"></li>
+                </ul>"#
+            ))
+            .create_async()
+            .await;
+        let _m2 = server
+            .mock("GET", "/sticker/10/animation.png")
+            .with_status(200)
+            .with_body(&apng)
+            .create_async()
+            .await;
+        let _m3 = server
+            .mock("GET", "/stickershop/v1/product/1/android/productInfo.meta")
+            .with_status(404)
+            .create_async()
+            .await;
+
+        let output = Path::new("Test Convert GIF Output");
+        let config = Config {
+            output: output.to_path_buf(),
+            cdn_url: url.clone(),
+            convert: Some(Convert::Gif),
+            ..Config::default()
+        };
+        let source_url = format!("{}/stickershop/product/1/en", url);
+        let actual = download_stickers(&config, &source_url).await;
+
+        let directory = output.join("Test Convert GIF");
+        let gif = std::fs::read(directory.join("01_10.gif"));
+        let manifest = std::fs::read(directory.join("pack.json"));
+        delete_directory_if_exists(output);
+        assert!(actual.is_ok(), "{}", actual.unwrap_err());
+
+        assert!(gif.unwrap().starts_with(b"GIF89a"));
+        let manifest: Value = serde_json::from_slice(&manifest.unwrap()).unwrap();
+        let files = manifest["stickers"][0]["files"].as_array().unwrap();
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0]["file_name"], "01_10.png");
+        assert_eq!(files[1]["file_name"], "01_10.gif");
+        assert_eq!(files[1]["asset"], "image");
+    }
+
+    #[tokio::test]
+    async fn test_download_stickers_export_telegram() {
+        let mut server = mockito::Server::new_async().await;
+        let url = server.url();
+
+        let mut png = std::io::Cursor::new(Vec::new());
+        image::RgbaImage::new(370, 320)
+            .write_to(&mut png, image::ImageFormat::Png)
+            .unwrap();
+
+        let _m = server
+            .mock("GET", "/stickershop/product/1/en")
+            .with_status(200)
+            .with_header("content-type", "text/html;charset=UTF-8")
+            .with_body(format!(
+                r#"<p data-test="sticker-name-title">Test Export Telegram</p>
+                <ul>
+                    <li class="FnStickerPreviewItem" data-preview="{{ &quot;type&quot; : &quot;static&quot;, &quot;id&quot; : &quot;10&quot;, &quot;staticUrl&quot; : &quot;{url}/sticker/10/static.png&quot; }}"></li>
+                </ul>"#
+            ))
+            .create_async()
+            .await;
+        let _m2 = server
+            .mock("GET", "/sticker/10/static.png")
+            .with_status(200)
+            .with_body(png.get_ref())
+            .create_async()
+            .await;
+        let _m3 = server
+            .mock("GET", "/stickershop/v1/product/1/android/productInfo.meta")
+            .with_status(404)
+            .create_async()
+            .await;
+
+        let output = Path::new("Test Export Telegram Output");
+        let config = Config {
+            output: output.to_path_buf(),
+            cdn_url: url.clone(),
+            export: vec![Export::Telegram],
+            ..Config::default()
+        };
+        let source_url = format!("{}/stickershop/product/1/en", url);
+        let actual = download_stickers(&config, &source_url).await;
+
+        let directory = output.join("Test Export Telegram").join("telegram");
+        let sticker = image::open(directory.join("01_10.png"));
+        let descriptor = std::fs::read(directory.join("stickers.json"));
+        delete_directory_if_exists(output);
+        assert!(actual.is_ok(), "{}", actual.unwrap_err());
+
+        let sticker = sticker.unwrap();
+        assert_eq!((sticker.width(), sticker.height()), (512, 443));
+        let descriptor: Value = serde_json::from_slice(&descriptor.unwrap()).unwrap();
+        assert_eq!(descriptor["title"], "Test Export Telegram");
+        assert_eq!(descriptor["stickers"][0]["sticker"], "01_10.png");
+    }
+
+    #[tokio::test]
+    async fn test_download_stickers_popup() {
+        let mut server = mockito::Server::new_async().await;
+        let url = server.url();
+
+        let _m = server
+            .mock("GET", "/test")
+            .with_status(200)
+            .with_header("content-type", "text/html;charset=UTF-8")
+            .with_body(format!(
+                r#"<p data-test="sticker-name-title">Test Popup</p>
+                <ul>
+                    <li class="FnStickerPreviewItem" data-preview="{{ &quot;type&quot; : &quot;popup&quot;, &quot;id&quot; : &quot;10&quot;, &quot;staticUrl&quot; : &quot;{url}/sticker/10/static.png&quot;, &quot;animationUrl&quot; : &quot;{url}/sticker/10/animation.png&quot;, &quot;popupUrl&quot; : &quot;{url}/sticker/10/popup.png&quot;, &quot;soundUrl&quot; : &quot;&quot; }}"></li>
+                </ul>"#
+            ))
+            .create_async()
+            .await;
+        let _m2 = server
+            .mock("GET", "/sticker/10/animation.png")
+            .with_status(200)
+            .with_body(PNG_BYTES)
+            .expect(1)
+            .create_async()
+            .await;
+        let _m3 = server
+            .mock("GET", "/sticker/10/popup.png")
+            .with_status(200)
+            .with_body(PNG_BYTES)
+            .expect(2)
+            .create_async()
+            .await;
+
+        let output = Path::new("Test Popup Output");
+        let config = Config {
+            output: output.to_path_buf(),
+            force: true,
+            ..Config::default()
+        };
+        let actual = download_stickers(&config, &format!("{}/test", url)).await;
+        assert!(actual.is_ok(), "{}", actual.unwrap_err());
+
+        let directory = output.join("Test Popup");
+        assert!(directory.join("01_10.png").is_file());
+        assert!(directory.join("01_10_popup.png").is_file());
+
+        let manifest: Value =
+            serde_json::from_slice(&std::fs::read(directory.join("pack.json")).unwrap()).unwrap();
+        let files = manifest["stickers"][0]["files"].as_array().unwrap();
+        assert_eq!(files[1]["file_name"], "01_10_popup.png");
+        assert_eq!(files[1]["asset"], "popup");
+        delete_directory_if_exists(output);
+
+        // Only the popup is downloaded when the assets are filtered.
+        let config = Config {
+            assets: vec![Asset::Popup],
+            ..config
+        };
+        let actual = download_stickers(&config, &format!("{}/test", url)).await;
+        assert!(actual.is_ok(), "{}", actual.unwrap_err());
+        assert!(!output.join("Test Popup").join("01_10.png").exists());
+        assert!(output.join("Test Popup").join("01_10_popup.png").is_file());
+        delete_directory_if_exists(output);
+
+        _m2.assert_async().await;
+        _m3.assert_async().await;
+
+        // A template without {suffix} would write the popup over the image.
+        let config = Config {
+            assets: vec![Asset::Image, Asset::Popup],
+            template: PathTemplate::parse("{title}/{sticker_id}.{ext}").unwrap(),
+            ..config
+        };
+        let actual = download_stickers(&config, &format!("{}/test", url)).await;
+        assert!(actual.unwrap_err().to_string().contains("{suffix}"));
+        delete_directory_if_exists(output);
+    }
+
+    #[tokio::test]
+    async fn test_download_stickers_all_variants() {
+        let mut server = mockito::Server::new_async().await;
+        let url = server.url();
+
+        let _m = server
+            .mock("GET", "/test")
+            .with_status(200)
+            .with_header("content-type", "text/html;charset=UTF-8")
+            .with_body(format!(
+                r#"<p data-test="sticker-name-title">Test All Variants</p>
+                <ul>
+                    <li class="FnStickerPreviewItem" data-preview="{{ &quot;type&quot; : &quot;animation_sound&quot;, &quot;id&quot; : &quot;10&quot;, &quot;staticUrl&quot; : &quot;{url}/sticker/10/static.png&quot;, &quot;fallbackStaticUrl&quot; : &quot;{url}/sticker/10/fallback.png&quot;, &quot;animationUrl&quot; : &quot;{url}/sticker/10/animation.png&quot;, &quot;popupUrl&quot; : &quot;&quot;, &quot;soundUrl&quot; : &quot;{url}/sticker/10/sound.m4a&quot; }}"></li>
+                </ul>"#
+            ))
+            .create_async()
+            .await;
+        let _m2 = server
+            .mock(
+                "GET",
+                mockito::Matcher::Regex(r"^/sticker/10/\w+\.png$".to_string()),
+            )
+            .with_status(200)
+            .with_body(PNG_BYTES)
+            .expect(3)
+            .create_async()
+            .await;
+        let _m3 = server
+            .mock("GET", "/sticker/10/sound.m4a")
+            .with_status(200)
+            .with_body(M4A_BYTES)
+            .create_async()
+            .await;
+
+        let output = Path::new("Test All Variants Output");
+        let config = Config {
+            output: output.to_path_buf(),
+            force: true,
+            assets: Asset::ALL_VARIANTS.to_vec(),
+            ..Config::default()
+        };
+        let actual = download_stickers(&config, &format!("{}/test", url)).await;
+        assert!(actual.is_ok(), "{}", actual.unwrap_err());
+
+        let directory = output.join("Test All Variants");
+        let mut file_names = std::fs::read_dir(&directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        file_names.sort();
+        delete_directory_if_exists(output);
+
+        assert_eq!(
+            file_names,
+            [
+                "01_10.m4a",
+                "01_10_anim.png",
+                "01_10_fallback.png",
+                "01_10_static.png",
+                "pack.json"
+            ]
+        );
+        _m2.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_download_stickers_zip_source_fallback() {
+        let mut server = mockito::Server::new_async().await;
+        let url = server.url();
+
+        let _m = server
+            .mock(
+                "GET",
+                mockito::Matcher::Regex(r"^/stickershop/v1/".to_string()),
+            )
+            .with_status(404)
+            .expect(3)
+            .create_async()
+            .await;
+        let _m2 = server
+            .mock("GET", "/stickershop/product/5/en")
+            .with_status(200)
+            .with_header("content-type", "text/html;charset=UTF-8")
+            .with_body(format!(
+                r#"<p data-test="sticker-name-title">Test Zip Fallback</p>
+                <ul>
+                    <li class="FnStickerPreviewItem" data-preview="{{ &quot;type&quot; : &quot;static&quot;, &quot;id&quot; : &quot;10&quot;, &quot;staticUrl&quot; : &quot;{url}/sticker/10.png&quot;, &quot;animationUrl&quot; : &quot;&quot;, &quot;soundUrl&quot; : &quot;&quot; }}"></li>
+                </ul>"#
+            ))
+            .create_async()
+            .await;
+        let _m3 = server
+            .mock("GET", "/sticker/10.png")
+            .with_status(200)
+            .with_body(PNG_BYTES)
+            .create_async()
+            .await;
+
+        let output = Path::new("Test Zip Fallback Output");
+        let config = Config {
+            output: output.to_path_buf(),
+            force: true,
+            source: Source::Zip,
+            cdn_url: url.clone(),
+            ..Config::default()
+        };
+        let actual = download_stickers(&config, &format!("{}/stickershop/product/5/en", url)).await;
+        assert!(actual.is_ok(), "{}", actual.unwrap_err());
+
+        let file_path = output.join("Test Zip Fallback").join("01_10.png");
+        let exists = file_path.is_file();
+        delete_directory_if_exists(output);
+        assert!(exists, "File '{}' does not exist", file_path.display());
+        _m.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_download_stickers_text_stickers() {
+        let mut server = mockito::Server::new_async().await;
+        let url = server.url();
+
+        let _m = server
+            .mock("GET", "/test")
+            .with_status(200)
+            .with_header("content-type", "text/html;charset=UTF-8")
+            .with_body(format!(
+                r#"<p data-test="sticker-name-title">Test Text Stickers</p>
+                <ul>
+                    <li class="FnStickerPreviewItem" data-preview="{{ &quot;type&quot; : &quot;per_sticker_text&quot;, &quot;id&quot; : &quot;10&quot;, &quot;staticUrl&quot; : &quot;{url}/sticker/10/sample.png&quot;, &quot;baseUrl&quot; : &quot;{url}/sticker/10/base.png&quot;, &quot;textArea&quot; : {{ &quot;x&quot; : 10, &quot;y&quot; : 30, &quot;width&quot; : 100, &quot;height&quot; : 30 }} }}"></li>
+                    <li class="FnStickerPreviewItem" data-preview="{{ &quot;type&quot; : &quot;sticker_custom&quot;, &quot;id&quot; : &quot;11&quot;, &quot;staticUrl&quot; : &quot;{url}/sticker/11/sample.png&quot;, &quot;baseUrl&quot; : &quot;{url}/sticker/11/base.png&quot;, &quot;overlayUrl&quot; : &quot;{url}/sticker/11/overlay.png&quot; }}"></li>
+                </ul>"#
+            ))
+            .create_async()
+            .await;
+
+        let mut base_image = std::io::Cursor::new(Vec::new());
+        image::RgbaImage::from_pixel(120, 90, image::Rgba([255, 255, 255, 255]))
+            .write_to(&mut base_image, image::ImageFormat::Png)
+            .unwrap();
+        let _m2 = server
+            .mock(
+                "GET",
+                mockito::Matcher::Regex(r"^/sticker/1\d/(base|overlay)\.png$".to_string()),
+            )
+            .with_status(200)
+            .with_body(base_image.into_inner())
+            .expect(3)
+            .create_async()
+            .await;
+        let m3 = server
+            .mock("GET", mockito::Matcher::Regex(r"/sample\.png$".to_string()))
+            .expect(0)
+            .create_async()
+            .await;
+
+        let output = Path::new("Test Text Stickers Output");
+        let config = Config {
+            output: output.to_path_buf(),
+            force: true,
+            sample_text: Some("Hello".to_string()),
+            font: Some(PathBuf::from("tests/fixtures/box.ttf")),
+            ..Config::default()
+        };
+        let actual = download_stickers(&config, &format!("{}/test", url)).await;
+        let directory = output.join("Test Text Stickers");
+        let exists = [
+            directory.join("01_10.png").is_file(),
+            directory.join("02_11.png").is_file(),
+            directory.join("02_11_overlay.png").is_file(),
+        ];
+        let samples = [
+            directory.join("01_10_sample.png").is_file(),
+            directory.join("02_11_sample.png").is_file(),
+        ];
+        let manifest = std::fs::read(directory.join("pack.json"));
+        delete_directory_if_exists(output);
+
+        assert!(actual.is_ok(), "{}", actual.unwrap_err());
+        assert_eq!(exists, [true, true, true]);
+        assert_eq!(samples, [true, false]);
+
+        let manifest: Value = serde_json::from_slice(&manifest.unwrap()).unwrap();
+        assert_eq!(manifest["stickers"][0]["text_area"]["width"], 100);
+        assert!(manifest["stickers"][1]["text_area"].is_null());
+        assert_eq!(manifest["stickers"][1]["files"][1]["asset"], "overlay");
+        _m2.assert_async().await;
+        m3.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_run_download_with_url() {
+        let mut server = mockito::Server::new_async().await;
+        let base_url = server.url();
+
+        let _m = server
+            .mock("GET", "/test")
+            .with_status(200)
+            .with_header("content-type", "text/html;charset=UTF-8")
+            .with_body(
+                r#"
+                <p class="mdCMN38Item01Ttl" data-test="sticker-name-title">Test Stickers</p>
+            "#,
+            )
+            .create_async()
+            .await;
+
+        let cli = Cli::try_parse_from(["program_name", "download", &format!("{}/test", base_url)])
+            .unwrap();
+
+        let result = run(&base_url, &base_url, cli).await;
+        assert!(result.is_ok(), "Failed to run: {:?}", result.err());
+
+        let dir_path = std::path::Path::new("Test Stickers");
+        assert!(
+            !dir_path.exists(),
+            "Directory 'Test Stickers' should not exist because no download happened."
+        );
+    }
+
+    #[tokio::test]
+    async fn test_run_download_with_invalid_url() {
+        let mut server = mockito::Server::new_async().await;
+        let base_url = server.url();
+
+        let _m = server
+            .mock("GET", "/test")
+            .with_status(200)
+            .with_header("content-type", "text/html;charset=UTF-8")
+            .with_body(
+                r#"
+                <div></div>
+            "#,
+            )
+            .create_async()
+            .await;
+
+        let cli = Cli::try_parse_from(["program_name", "download", &format!("{}/test", base_url)])
+            .unwrap();
+
+        let result = run(&base_url, &base_url, cli).await;
+        assert!(result.is_err());
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Failed to fetch stickers: Could not find the sticker-name-title in the document. Please check that the URL points to a valid sticker page."
+        );
+    }
+
+    #[tokio::test]
+    async fn test_run_search() {
+        let mut server = mockito::Server::new_async().await;
+        let base_url = server.url();
+
+        let _m = server
+            .mock("GET", "/api/search/sticker?category=sticker&type=ALL&offset=0&limit=36&includeFacets=false&query=test")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"totalCount":1,"items":[{"productUrl": "/test"}]}"#)
+            .create_async()
+            .await;
+
+        let _m2 = server
+            .mock("GET", "/test")
+            .with_status(200)
+            .with_header("content-type", "text/html;charset=UTF-8")
+            .with_body(
+                r#"
+                <p class="mdCMN38Item01Ttl" data-test="sticker-name-title">Test Search Results</p>
+            "#,
+            )
+            .create_async()
+            .await;
+
+        let cli = Cli::try_parse_from(["program_name", "search", "test"]).unwrap();
+
+        let result = run(&base_url, &base_url, cli).await;
+        assert!(result.is_ok(), "Failed to run: {:?}", result.err());
+
+        let dir_path = std::path::Path::new("Test Search Results");
+        assert!(
+            !dir_path.exists(),
+            "Directory 'Test Search Results' should not exist because no download happened."
+        );
+    }
+
+    #[tokio::test]
+    async fn test_run_search_emoji() {
+        let mut server = mockito::Server::new_async().await;
+        let url = server.url();
+
+        let _m = server
+            .mock("GET", "/api/search/emoji?category=emoji&type=ALL&offset=0&limit=36&includeFacets=false&query=miku")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"totalCount":1,"items":[{"productUrl": "/emojishop/product/5ac1bfd5040ab15980c9b435/en"}]}"#)
+            .create_async()
+            .await;
+        let _m2 = server
+            .mock("GET", "/emojishop/product/5ac1bfd5040ab15980c9b435/en")
+            .with_status(200)
+            .with_header("content-type", "text/html;charset=UTF-8")
+            .with_body(format!(
+                r#"<p data-test="emoji-name-title">Test Emoji</p>
+                <a data-test="emoji-author">Emoji Author</a>
+                <ul>
+                    <li class="FnStickerPreviewItem" data-preview="{{ &quot;type&quot; : &quot;static&quot;, &quot;id&quot; : &quot;5ac1bfd5040ab15980c9b435_001&quot;, &quot;staticUrl&quot; : &quot;{url}/sticon/001.png&quot;, &quot;animationUrl&quot; : &quot;&quot; }}"></li>
+                    <li class="FnStickerPreviewItem" data-preview="{{ &quot;type&quot; : &quot;animation&quot;, &quot;id&quot; : &quot;5ac1bfd5040ab15980c9b435_002&quot;, &quot;staticUrl&quot; : &quot;{url}/sticon/002.png&quot;, &quot;animationUrl&quot; : &quot;{url}/sticon/002_animation.png&quot; }}"></li>
+                </ul>"#
+            ))
+            .create_async()
+            .await;
+        let _m3 = server
+            .mock(
+                "GET",
+                mockito::Matcher::Regex(r"^/sticon/00\d(_animation)?\.png$".to_string()),
+            )
+            .with_status(200)
+            .with_body(PNG_BYTES)
+            .expect(2)
+            .create_async()
+            .await;
+        let m4 = server
+            .mock(
+                "GET",
+                mockito::Matcher::Regex(r"^/stickershop/v1/".to_string()),
+            )
+            .expect(0)
+            .create_async()
+            .await;
+
+        let output = Path::new("Test Emoji Output");
+        let cli = Cli::try_parse_from([
+            "program_name",
+            "search",
+            "--category",
+            "emoji",
+            "miku",
+            "--force",
+            "--output",
+            output.to_str().unwrap(),
+            "--template",
+            "{author}/{title}/{index:02}_{sticker_id}{suffix}.{ext}",
+        ])
+        .unwrap();
+        let result = run(&url, &url, cli).await;
+        assert!(result.is_ok(), "Failed to run: {:?}", result.err());
+
+        let directory = output.join("Emoji Author").join("Test Emoji");
+        let exists = [
+            directory
+                .join("01_5ac1bfd5040ab15980c9b435_001.png")
+                .is_file(),
+            directory
+                .join("02_5ac1bfd5040ab15980c9b435_002.png")
+                .is_file(),
+        ];
+        delete_directory_if_exists(output);
+        assert_eq!(exists, [true, true]);
+        _m3.assert_async().await;
+        m4.assert_async().await;
     }
 }
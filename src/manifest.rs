@@ -0,0 +1,150 @@
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::text::TextArea;
+
+pub const MANIFEST_FILE_NAME: &str = "pack.json";
+
+/// Metadata of a downloaded pack, written as `pack.json` next to its stickers so it can be
+/// indexed without scraping the store again.
+#[derive(Debug, Serialize)]
+pub struct PackManifest {
+    pub pack_id: Option<String>,
+    pub title: String,
+    pub author: Option<String>,
+    pub description: Option<String>,
+    pub lang: String,
+    pub source_url: String,
+    /// RFC 3339 timestamp of the download.
+    pub downloaded_at: String,
+    pub stickers: Vec<StickerManifest>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StickerManifest {
+    pub id: String,
+    /// Position of the sticker within the pack, starting at 1.
+    pub order: usize,
+    #[serde(rename = "type")]
+    pub sticker_type: Option<String>,
+    /// Dimensions from the pack metadata, if LINE publishes it.
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    /// Where custom and message stickers draw the user's text, if the store page says.
+    pub text_area: Option<TextArea>,
+    /// Every non-empty url of the sticker's `data-preview`, keyed by its field name.
+    pub urls: BTreeMap<String, String>,
+    pub files: Vec<FileManifest>,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct FileManifest {
+    /// Path of the file relative to the manifest, using `/` as separator.
+    pub file_name: String,
+    /// Kind of the file, e.g. `image`, `sound` or `popup`.
+    pub asset: String,
+    pub url: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+impl FileManifest {
+    pub fn from_file(
+        pack_directory: &Path,
+        path: &Path,
+        url: &str,
+        asset: &str,
+    ) -> std::io::Result<Self> {
+        let contents = std::fs::read(path)?;
+        let relative_path = path.strip_prefix(pack_directory).unwrap_or(path);
+        let file_name = relative_path
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+
+        Ok(FileManifest {
+            file_name,
+            asset: asset.to_string(),
+            url: url.to_string(),
+            size: contents.len() as u64,
+            sha256: format!("{:x}", Sha256::digest(&contents)),
+        })
+    }
+}
+
+/// Returns the deepest directory containing every given file, which is where the
+/// manifest of a pack is written.
+pub fn pack_directory<'a>(mut paths: impl Iterator<Item = &'a Path>) -> Option<PathBuf> {
+    let mut directory = paths.next()?.parent()?.to_path_buf();
+    for path in paths {
+        while !path.starts_with(&directory) {
+            if !directory.pop() {
+                return None;
+            }
+        }
+    }
+
+    Some(directory)
+}
+
+pub fn write_manifest(
+    directory: &Path,
+    manifest: &PackManifest,
+) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let path = directory.join(MANIFEST_FILE_NAME);
+    let json = serde_json::to_vec_pretty(manifest)?;
+    crate::write_file_atomically(&path, &json)?;
+
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pack_directory() {
+        let paths = [
+            Path::new("out/Author/Title/01_1.png"),
+            Path::new("out/Author/Title/sounds/01_1.m4a"),
+        ];
+        let actual = pack_directory(paths.into_iter());
+        assert_eq!(actual.unwrap(), Path::new("out/Author/Title"));
+
+        let paths = [
+            Path::new("out/Title/static/1.png"),
+            Path::new("out/Title/animation/1.png"),
+        ];
+        let actual = pack_directory(paths.into_iter());
+        assert_eq!(actual.unwrap(), Path::new("out/Title"));
+
+        assert!(pack_directory(std::iter::empty()).is_none());
+    }
+
+    #[test]
+    fn test_file_manifest_from_file() {
+        let directory = Path::new("Test File Manifest");
+        std::fs::create_dir_all(directory.join("sounds")).unwrap();
+        let path = directory.join("sounds").join("1.m4a");
+        std::fs::write(&path, b"abc").unwrap();
+
+        let actual =
+            FileManifest::from_file(directory, &path, "https://example.com/1.m4a", "sound");
+        std::fs::remove_dir_all(directory).unwrap();
+
+        assert_eq!(
+            actual.unwrap(),
+            FileManifest {
+                file_name: "sounds/1.m4a".to_string(),
+                asset: "sound".to_string(),
+                url: "https://example.com/1.m4a".to_string(),
+                size: 3,
+                sha256: "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+                    .to_string(),
+            }
+        );
+    }
+}
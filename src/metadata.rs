@@ -0,0 +1,183 @@
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+use crate::{ensure_success, fetch, Config};
+
+pub const META_FILE_NAME: &str = "productInfo.meta";
+
+/// Metadata of a pack as published by LINE in `productInfo.meta`, both on the CDN and
+/// inside the pack archives.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProductInfo {
+    pub package_id: u64,
+    /// Title keyed by language, e.g. `en`, `ja` or `zh-Hant`.
+    #[serde(default)]
+    pub title: BTreeMap<String, String>,
+    /// Author keyed by language.
+    #[serde(default)]
+    pub author: BTreeMap<String, String>,
+    #[serde(default)]
+    pub stickers: Vec<StickerInfo>,
+    #[serde(default)]
+    pub has_animation: bool,
+    #[serde(default)]
+    pub has_sound: bool,
+    /// E.g. `STATIC`, `ANIMATION_SOUND` or `POPUP`.
+    pub sticker_resource_type: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct StickerInfo {
+    pub id: u64,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+}
+
+impl ProductInfo {
+    pub fn parse(json: &[u8]) -> Result<ProductInfo, String> {
+        serde_json::from_slice(json).map_err(|err| format!("Invalid {}: {}", META_FILE_NAME, err))
+    }
+
+    pub fn title(&self, lang: &str) -> Option<String> {
+        localized(&self.title, lang)
+    }
+
+    pub fn author(&self, lang: &str) -> Option<String> {
+        localized(&self.author, lang)
+    }
+
+    /// The resource type in the same spelling as the `type` of the store page, e.g. `animation_sound`.
+    pub fn sticker_type(&self) -> Option<String> {
+        self.sticker_resource_type.as_deref().map(str::to_lowercase)
+    }
+
+    pub fn sticker(&self, id: &str) -> Option<&StickerInfo> {
+        self.stickers
+            .iter()
+            .find(|sticker| sticker.id.to_string() == id)
+    }
+}
+
+pub fn product_info_url(cdn_url: &str, pack_id: &str) -> String {
+    format!(
+        "{}/stickershop/v1/product/{}/android/{}",
+        cdn_url, pack_id, META_FILE_NAME
+    )
+}
+
+/// Fetches the metadata of a pack from the CDN. Returns `Ok(None)` if the CDN has none,
+/// in which case the metadata has to be scraped from the store page.
+pub async fn fetch_product_info(
+    config: &Config,
+    pack_id: &str,
+) -> Result<Option<ProductInfo>, Box<dyn std::error::Error>> {
+    let url = product_info_url(&config.cdn_url, pack_id);
+    let response = fetch(config, &url).await?;
+    if matches!(response.status.as_u16(), 403 | 404) {
+        return Ok(None);
+    }
+    ensure_success(&url, &response)?;
+
+    Ok(Some(ProductInfo::parse(&response.body)?))
+}
+
+/// Returns the value for the given language, falling back to English and then to any
+/// language the pack has.
+fn localized(values: &BTreeMap<String, String>, lang: &str) -> Option<String> {
+    values
+        .get(lang)
+        .or_else(|| values.get("en"))
+        .or_else(|| values.values().next())
+        .cloned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PRODUCT_INFO: &str = r#"{"packageId":6920150,"onSale":true,"validDays":0,"title":{"en":"Hatsune Miku","ja":"初音ミク"},"author":{"en":"CRYPTON"},"price":[{"country":"@@","currency":"NLC","symbol":"NLC","price":50.0}],"stickers":[{"id":20578528,"width":320,"height":296},{"id":20578529,"width":296,"height":320}],"hasAnimation":true,"hasSound":true,"stickerResourceType":"ANIMATION_SOUND"}"#;
+
+    #[test]
+    fn test_parse_product_info() {
+        let actual = ProductInfo::parse(PRODUCT_INFO.as_bytes()).unwrap();
+        assert_eq!(actual.package_id, 6920150);
+        assert_eq!(actual.title("ja").unwrap(), "初音ミク");
+        assert_eq!(actual.author("ja").unwrap(), "CRYPTON");
+        assert_eq!(actual.sticker_type().unwrap(), "animation_sound");
+        assert!(actual.has_animation);
+        assert!(actual.has_sound);
+        assert_eq!(
+            actual.sticker("20578529"),
+            Some(&StickerInfo {
+                id: 20578529,
+                width: Some(296),
+                height: Some(320),
+            })
+        );
+        assert!(actual.sticker("1").is_none());
+
+        assert!(ProductInfo::parse(b"<html></html>").is_err());
+        assert!(ProductInfo::parse(br#"{"title":{"en":"No ID"}}"#).is_err());
+    }
+
+    #[test]
+    fn test_localized() {
+        let values = BTreeMap::from([
+            ("en".to_string(), "English".to_string()),
+            ("ja".to_string(), "Japanese".to_string()),
+        ]);
+        assert_eq!(localized(&values, "ja").unwrap(), "Japanese");
+        assert_eq!(localized(&values, "ko").unwrap(), "English");
+
+        let values = BTreeMap::from([("ja".to_string(), "Japanese".to_string())]);
+        assert_eq!(localized(&values, "en").unwrap(), "Japanese");
+        assert!(localized(&BTreeMap::new(), "en").is_none());
+    }
+
+    #[test]
+    fn test_product_info_url() {
+        assert_eq!(
+            product_info_url("https://stickershop.line-scdn.net", "6920150"),
+            "https://stickershop.line-scdn.net/stickershop/v1/product/6920150/android/productInfo.meta"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fetch_product_info() {
+        let mut server = mockito::Server::new_async().await;
+
+        let _m = server
+            .mock(
+                "GET",
+                "/stickershop/v1/product/6920150/android/productInfo.meta",
+            )
+            .with_status(200)
+            .with_body(PRODUCT_INFO)
+            .create_async()
+            .await;
+        let _m2 = server
+            .mock("GET", "/stickershop/v1/product/1/android/productInfo.meta")
+            .with_status(404)
+            .create_async()
+            .await;
+        let _m3 = server
+            .mock("GET", "/stickershop/v1/product/2/android/productInfo.meta")
+            .with_status(200)
+            .with_body("{}")
+            .create_async()
+            .await;
+
+        let config = Config {
+            cdn_url: server.url(),
+            ..Config::default()
+        };
+        let actual = fetch_product_info(&config, "6920150").await.unwrap();
+        assert_eq!(actual.unwrap().title("en").unwrap(), "Hatsune Miku");
+
+        let actual = fetch_product_info(&config, "1").await.unwrap();
+        assert!(actual.is_none());
+
+        assert!(fetch_product_info(&config, "2").await.is_err());
+    }
+}
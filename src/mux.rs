@@ -0,0 +1,331 @@
+use image::{Rgba, RgbaImage};
+use std::path::{Path, PathBuf};
+use tokio::process::Command;
+
+use crate::convert::{animation_plays, decode_apng, is_converted};
+use crate::export::write_apng;
+use crate::{Asset, Config, Mux};
+
+/// How long a still image is shown before it is repeated, it only needs to be shorter
+/// than any sound.
+const STILL_FRAME_DELAY_MS: f64 = 100.0;
+
+/// Returns the path the video of a sound sticker is written to, e.g. `01_123.mp4` for `01_123.m4a`.
+pub fn muxed_path(sound_path: &Path, format: Mux) -> PathBuf {
+    sound_path.with_extension(format.extension())
+}
+
+/// Combines the sound of a sticker with its animation, or its image if it has none, into
+/// a video that repeats the animation for as long as the sound plays. Returns the sound
+/// and the video, or `Ok(None)` if the sticker has no sound or nothing to show with it.
+pub async fn mux_sticker<'a>(
+    config: &Config,
+    format: Mux,
+    files: impl Iterator<Item = (Asset, &'a PathBuf)> + Clone,
+) -> Result<Option<(PathBuf, PathBuf)>, Box<dyn std::error::Error>> {
+    let find = |preferred: Asset| files.clone().find(|(asset, _)| *asset == preferred);
+    let Some((_, sound_path)) = find(Asset::Sound) else {
+        return Ok(None);
+    };
+    let Some((_, image_path)) = [Asset::Animation, Asset::Image, Asset::Static]
+        .into_iter()
+        .find_map(find)
+        .filter(|(_, path)| path.extension().and_then(|ext| ext.to_str()) == Some("png"))
+    else {
+        return Ok(None);
+    };
+
+    let output_path = muxed_path(sound_path, format);
+    if !config.force
+        && is_converted(sound_path, &output_path)
+        && is_converted(image_path, &output_path)
+    {
+        if config.verbose {
+            println!("Skipping {} (up to date)", output_path.display());
+        }
+        return Ok(Some((sound_path.clone(), output_path)));
+    }
+
+    let data = std::fs::read(image_path)?;
+    let frames = match animation_plays(&data) {
+        Some(_) => decode_apng(&data),
+        None => image::load_from_memory(&data)
+            .map(|image| vec![(image.to_rgba8(), STILL_FRAME_DELAY_MS)]),
+    }
+    .map_err(|err| format!("Could not read {}: {}", image_path.display(), err))?;
+    let mut frames_path = output_path.clone().into_os_string();
+    frames_path.push(".frames.png");
+    let mut temp_path = output_path.clone().into_os_string();
+    temp_path.push(".part");
+    std::fs::write(
+        &frames_path,
+        write_apng(&prepare_frames(frames, format), 0)?,
+    )?;
+
+    let output = Command::new(&config.ffmpeg)
+        .args(["-y", "-loglevel", "error"])
+        .args(["-f", "apng", "-ignore_loop", "0", "-i"])
+        .arg(&frames_path)
+        .arg("-i")
+        .arg(sound_path)
+        .args(["-map", "0:v:0", "-map", "1:a:0", "-shortest"])
+        .args(encoder_args(format))
+        .arg(&temp_path)
+        .output()
+        .await;
+    std::fs::remove_file(&frames_path)?;
+    let output = output.map_err(|err| {
+        format!(
+            "Muxing sound stickers needs ffmpeg, could not run {}: {}",
+            config.ffmpeg.display(),
+            err
+        )
+    })?;
+    if !output.status.success() {
+        let _ = std::fs::remove_file(&temp_path);
+        return Err(format!(
+            "ffmpeg could not mux {}: {}",
+            sound_path.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )
+        .into());
+    }
+    std::fs::rename(&temp_path, &output_path)?;
+
+    if config.verbose {
+        println!("Muxed {}", output_path.display());
+    }
+    Ok(Some((sound_path.clone(), output_path)))
+}
+
+/// Codecs of a video: H.264 and the AAC of the sound as is for MP4, VP9 with alpha and
+/// Opus for WebM.
+fn encoder_args(format: Mux) -> &'static [&'static str] {
+    match format {
+        Mux::Mp4 => &[
+            "-c:v",
+            "libx264",
+            "-pix_fmt",
+            "yuv420p",
+            "-c:a",
+            "copy",
+            "-movflags",
+            "+faststart",
+            "-f",
+            "mp4",
+        ],
+        Mux::Webm => &[
+            "-c:v",
+            "libvpx-vp9",
+            "-pix_fmt",
+            "yuva420p",
+            "-b:v",
+            "0",
+            "-crf",
+            "32",
+            "-c:a",
+            "libopus",
+            "-f",
+            "webm",
+        ],
+    }
+}
+
+/// Pads frames to even dimensions, which 4:2:0 video needs, and puts them on white for
+/// MP4, which has no transparency. A still image becomes two frames so it can be looped.
+fn prepare_frames(mut frames: Vec<(RgbaImage, f64)>, format: Mux) -> Vec<(RgbaImage, f64)> {
+    if let [(image, _)] = frames.as_slice() {
+        let image = image.clone();
+        frames = vec![
+            (image.clone(), STILL_FRAME_DELAY_MS),
+            (image, STILL_FRAME_DELAY_MS),
+        ];
+    }
+
+    let background = match format {
+        Mux::Mp4 => Rgba([255, 255, 255, 255]),
+        Mux::Webm => Rgba([0, 0, 0, 0]),
+    };
+    for (image, _) in &mut frames {
+        let mut canvas = RgbaImage::from_pixel(
+            image.width().next_multiple_of(2),
+            image.height().next_multiple_of(2),
+            background,
+        );
+        image::imageops::overlay(&mut canvas, image, 0, 0);
+        *image = canvas;
+    }
+
+    frames
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(width: u32, height: u32) -> (RgbaImage, f64) {
+        let mut image = RgbaImage::from_pixel(width, height, Rgba([255, 0, 0, 255]));
+        image.put_pixel(0, 0, Rgba([0, 0, 0, 0]));
+        (image, 50.0)
+    }
+
+    #[test]
+    fn test_muxed_path() {
+        assert_eq!(
+            muxed_path(Path::new("pack/01_1.m4a"), Mux::Mp4),
+            PathBuf::from("pack/01_1.mp4")
+        );
+        assert_eq!(
+            muxed_path(Path::new("01_1.m4a"), Mux::Webm),
+            PathBuf::from("01_1.webm")
+        );
+    }
+
+    #[test]
+    fn test_prepare_frames() {
+        let actual = prepare_frames(vec![frame(3, 2), frame(3, 2)], Mux::Mp4);
+        assert_eq!(actual.len(), 2);
+        assert_eq!(actual[0].0.dimensions(), (4, 2));
+        assert_eq!(actual[0].0.get_pixel(0, 0).0, [255, 255, 255, 255]);
+        assert_eq!(actual[0].0.get_pixel(3, 1).0, [255, 255, 255, 255]);
+        assert_eq!(actual[0].0.get_pixel(1, 1).0, [255, 0, 0, 255]);
+        assert_eq!(actual[1].1, 50.0);
+
+        let actual = prepare_frames(vec![frame(3, 3)], Mux::Webm);
+        assert_eq!(actual.len(), 2);
+        assert_eq!(actual[0].0.dimensions(), (4, 4));
+        assert_eq!(actual[0].0.get_pixel(0, 0).0, [0, 0, 0, 0]);
+        assert_eq!(actual[0].0.get_pixel(3, 3).0, [0, 0, 0, 0]);
+        assert_eq!(actual[1].1, STILL_FRAME_DELAY_MS);
+    }
+
+    async fn mux_test_sticker(
+        config: &Config,
+        name: &str,
+        assets: &[Asset],
+    ) -> (Result<Option<PathBuf>, String>, Vec<String>) {
+        let directory = Path::new(name);
+        std::fs::create_dir_all(directory).unwrap();
+        let image_path = directory.join("01_1.png");
+        let sound_path = directory.join("01_1.m4a");
+        let (image, _) = frame(4, 4);
+        std::fs::write(&image_path, crate::export::encode_png(&image).unwrap()).unwrap();
+        std::fs::write(&sound_path, b"not really sound").unwrap();
+
+        let files: Vec<(Asset, &PathBuf)> = assets
+            .iter()
+            .map(|asset| match asset {
+                Asset::Sound => (*asset, &sound_path),
+                _ => (*asset, &image_path),
+            })
+            .collect();
+        let actual = mux_sticker(config, Mux::Mp4, files.into_iter())
+            .await
+            .map(|muxed| muxed.map(|(_, output_path)| output_path))
+            .map_err(|err| err.to_string());
+        let mut remaining: Vec<String> = std::fs::read_dir(directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        remaining.sort();
+        std::fs::remove_dir_all(directory).unwrap();
+
+        (actual, remaining)
+    }
+
+    #[tokio::test]
+    async fn test_mux_sticker_without_sound() {
+        let (actual, _) = mux_test_sticker(
+            &Config::default(),
+            "Test Mux Without Sound",
+            &[Asset::Image],
+        )
+        .await;
+        assert_eq!(actual, Ok(None));
+
+        let (actual, _) =
+            mux_test_sticker(&Config::default(), "Test Mux Only Sound", &[Asset::Sound]).await;
+        assert_eq!(actual, Ok(None));
+    }
+
+    #[tokio::test]
+    async fn test_mux_sticker_without_ffmpeg() {
+        let config = Config {
+            ffmpeg: PathBuf::from("missing-ffmpeg-binary"),
+            ..Config::default()
+        };
+        let (actual, remaining) = mux_test_sticker(
+            &config,
+            "Test Mux Without FFmpeg",
+            &[Asset::Image, Asset::Sound],
+        )
+        .await;
+        let err = actual.unwrap_err();
+        assert!(err.contains("needs ffmpeg"), "{}", err);
+        // The frames handed to ffmpeg are cleaned up.
+        assert_eq!(remaining, ["01_1.m4a", "01_1.png"]);
+    }
+
+    #[tokio::test]
+    async fn test_mux_sticker_up_to_date() {
+        let directory = Path::new("Test Mux Up To Date");
+        std::fs::create_dir_all(directory).unwrap();
+        let image_path = directory.join("01_1.png");
+        let sound_path = directory.join("01_1.m4a");
+        std::fs::write(&image_path, b"").unwrap();
+        std::fs::write(&sound_path, b"").unwrap();
+        std::fs::write(directory.join("01_1.mp4"), b"").unwrap();
+
+        let config = Config {
+            ffmpeg: PathBuf::from("missing-ffmpeg-binary"),
+            ..Config::default()
+        };
+        let files = [(Asset::Image, &image_path), (Asset::Sound, &sound_path)];
+        let actual = mux_sticker(&config, Mux::Mp4, files.into_iter())
+            .await
+            .map_err(|err| err.to_string());
+        std::fs::remove_dir_all(directory).unwrap();
+        assert_eq!(
+            actual,
+            Ok(Some((sound_path.clone(), directory.join("01_1.mp4"))))
+        );
+    }
+
+    #[tokio::test]
+    #[ignore = "needs ffmpeg"]
+    async fn test_mux_sticker() {
+        let directory = Path::new("Test Mux");
+        std::fs::create_dir_all(directory).unwrap();
+        let image_path = directory.join("01_1.png");
+        let sound_path = directory.join("01_1.m4a");
+        let frames = vec![frame(4, 4), frame(4, 4)];
+        std::fs::write(&image_path, write_apng(&frames, 1).unwrap()).unwrap();
+        let status = std::process::Command::new("ffmpeg")
+            .args([
+                "-y",
+                "-loglevel",
+                "error",
+                "-f",
+                "lavfi",
+                "-i",
+                "sine=duration=1",
+            ])
+            .args(["-c:a", "aac"])
+            .arg(&sound_path)
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let files = [(Asset::Image, &image_path), (Asset::Sound, &sound_path)];
+        let actual = mux_sticker(&Config::default(), Mux::Mp4, files.into_iter())
+            .await
+            .map_err(|err| err.to_string());
+        let size = std::fs::metadata(directory.join("01_1.mp4")).map(|metadata| metadata.len());
+        std::fs::remove_dir_all(directory).unwrap();
+        assert_eq!(
+            actual,
+            Ok(Some((sound_path.clone(), directory.join("01_1.mp4"))))
+        );
+        assert!(size.unwrap() > 0);
+    }
+}
@@ -0,0 +1,241 @@
+use std::path::PathBuf;
+
+use crate::sanitize_directory_name;
+
+pub const DEFAULT_TEMPLATE: &str = "{title}/{index:02}_{sticker_id}{suffix}.{ext}";
+
+const PLACEHOLDERS: [&str; 9] = [
+    "pack_id",
+    "title",
+    "author",
+    "lang",
+    "sticker_id",
+    "index",
+    "type",
+    "suffix",
+    "ext",
+];
+
+/// Values a path template can refer to, one per placeholder.
+#[derive(Debug, Default)]
+pub struct PathValues<'a> {
+    pub pack_id: &'a str,
+    pub title: &'a str,
+    pub author: &'a str,
+    pub lang: &'a str,
+    pub sticker_id: &'a str,
+    /// Position of the sticker within the pack, starting at 1.
+    pub index: usize,
+    pub sticker_type: &'a str,
+    /// Distinguishes the files of one sticker that share an extension, e.g. `_popup`.
+    pub suffix: &'a str,
+    pub ext: &'a str,
+}
+
+impl PathValues<'_> {
+    fn get(&self, name: &str) -> String {
+        match name {
+            "pack_id" => self.pack_id.to_string(),
+            "title" => self.title.to_string(),
+            "author" => self.author.to_string(),
+            "lang" => self.lang.to_string(),
+            "sticker_id" => self.sticker_id.to_string(),
+            "index" => self.index.to_string(),
+            "type" => self.sticker_type.to_string(),
+            "suffix" => self.suffix.to_string(),
+            "ext" => self.ext.to_string(),
+            _ => unreachable!("placeholders are validated when the template is parsed"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Literal(String),
+    Placeholder { name: String, width: usize },
+}
+
+/// A relative file path with `{placeholder}` segments, e.g.
+/// `{author}/{pack_id} - {title}/{index:02}_{sticker_id}.{ext}`.
+///
+/// Placeholders may be zero padded to a minimum width with `{name:0N}`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PathTemplate {
+    components: Vec<Vec<Segment>>,
+}
+
+impl PathTemplate {
+    pub fn parse(template: &str) -> Result<PathTemplate, String> {
+        let mut components = Vec::new();
+        for component in template.split(['/', '\\']) {
+            if component.is_empty() {
+                return Err(format!(
+                    "Template {} contains an empty path component",
+                    template
+                ));
+            }
+            components.push(parse_component(component)?);
+        }
+
+        Ok(PathTemplate { components })
+    }
+
+    /// Renders the template into a relative path. Every substituted value is sanitized,
+    /// so values containing `/` or characters invalid in file names cannot change the layout.
+    pub fn render(&self, values: &PathValues) -> PathBuf {
+        self.components
+            .iter()
+            .map(|segments| render_component(segments, values))
+            .collect()
+    }
+}
+
+fn parse_component(component: &str) -> Result<Vec<Segment>, String> {
+    let mut segments = Vec::new();
+    let mut rest = component;
+
+    while let Some(start) = rest.find('{') {
+        if rest[..start].contains('}') {
+            return Err(format!("Unopened placeholder in {}", component));
+        }
+        if start > 0 {
+            segments.push(Segment::Literal(rest[..start].to_string()));
+        }
+
+        let end = rest[start..]
+            .find('}')
+            .map(|end| start + end)
+            .ok_or_else(|| format!("Unclosed placeholder in {}", component))?;
+        segments.push(parse_placeholder(&rest[start + 1..end])?);
+        rest = &rest[end + 1..];
+    }
+
+    if rest.contains('}') {
+        return Err(format!("Unopened placeholder in {}", component));
+    }
+    if !rest.is_empty() {
+        segments.push(Segment::Literal(rest.to_string()));
+    }
+
+    Ok(segments)
+}
+
+fn parse_placeholder(placeholder: &str) -> Result<Segment, String> {
+    let (name, format) = match placeholder.split_once(':') {
+        Some((name, format)) => (name, Some(format)),
+        None => (placeholder, None),
+    };
+
+    if !PLACEHOLDERS.contains(&name) {
+        return Err(format!(
+            "Unknown placeholder {{{}}}. Expected one of {}",
+            name,
+            PLACEHOLDERS.map(|name| format!("{{{}}}", name)).join(", ")
+        ));
+    }
+
+    let width = match format {
+        None => 0,
+        Some(format) => format
+            .strip_prefix('0')
+            .and_then(|width| width.parse::<usize>().ok())
+            .ok_or_else(|| {
+                format!(
+                    "Invalid format {} for {{{}}}. Expected a zero padded width like {{{}:02}}",
+                    format, name, name
+                )
+            })?,
+    };
+
+    Ok(Segment::Placeholder {
+        name: name.to_string(),
+        width,
+    })
+}
+
+fn render_component(segments: &[Segment], values: &PathValues) -> String {
+    let mut component = String::new();
+    for segment in segments {
+        match segment {
+            Segment::Literal(literal) => component.push_str(literal),
+            Segment::Placeholder { name, width } => {
+                let value = sanitize_directory_name(&values.get(name));
+                component.push_str(&format!("{:0>width$}", value, width = *width));
+            }
+        }
+    }
+
+    let component = component.trim().to_string();
+    if component.is_empty() || component == "." || component == ".." {
+        "_".to_string()
+    } else {
+        component
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    fn values() -> PathValues<'static> {
+        PathValues {
+            pack_id: "28170905",
+            title: "Ranma1/2: Stickers",
+            author: "Rumiko Takahashi",
+            lang: "en",
+            sticker_id: "716101654",
+            index: 3,
+            sticker_type: "animation",
+            suffix: "_popup",
+            ext: "png",
+        }
+    }
+
+    #[test]
+    fn test_render_default_template() {
+        let template = PathTemplate::parse(DEFAULT_TEMPLATE).unwrap();
+        let actual = template.render(&values());
+        assert_eq!(
+            actual,
+            Path::new("Ranma1_2 Stickers").join("03_716101654_popup.png")
+        );
+    }
+
+    #[test]
+    fn test_render_template() {
+        let template = PathTemplate::parse(
+            "{author}/{pack_id} - {title} [{lang}]/{index:02}_{sticker_id}_{type}.{ext}",
+        )
+        .unwrap();
+        let actual = template.render(&values());
+        assert_eq!(
+            actual,
+            Path::new("Rumiko Takahashi")
+                .join("28170905 - Ranma1_2 Stickers [en]")
+                .join("03_716101654_animation.png")
+        );
+    }
+
+    #[test]
+    fn test_render_template_empty_values() {
+        let template = PathTemplate::parse("{author}/{title}/{sticker_id}.{ext}").unwrap();
+        let values = PathValues {
+            title: "..",
+            author: "",
+            ..values()
+        };
+        let actual = template.render(&values);
+        assert_eq!(actual, Path::new("_").join("_").join("716101654.png"));
+    }
+
+    #[test]
+    fn test_parse_template_errors() {
+        assert!(PathTemplate::parse("{title}//{sticker_id}.{ext}").is_err());
+        assert!(PathTemplate::parse("{title}/{sticker_id.{ext}").is_err());
+        assert!(PathTemplate::parse("{title}/sticker_id}.{ext}").is_err());
+        assert!(PathTemplate::parse("{name}/{sticker_id}.{ext}").is_err());
+        assert!(PathTemplate::parse("{title}/{index:2}.{ext}").is_err());
+        assert!(PathTemplate::parse("{title}/{index:0x}.{ext}").is_err());
+    }
+}
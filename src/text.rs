@@ -0,0 +1,233 @@
+use ab_glyph::{Font, FontVec, PxScale, ScaleFont};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+
+/// Fonts tried in order when rendering a sample text without `--font`.
+const SYSTEM_FONTS: [&str; 6] = [
+    "/usr/share/fonts/truetype/dejavu/DejaVuSans.ttf",
+    "/usr/share/fonts/TTF/DejaVuSans.ttf",
+    "/usr/share/fonts/opentype/noto/NotoSansCJK-Regular.ttc",
+    "/System/Library/Fonts/Supplemental/Arial.ttf",
+    "/Library/Fonts/Arial.ttf",
+    "C:\\Windows\\Fonts\\arial.ttf",
+];
+
+/// Stickers whose text is chosen by the user in the LINE app and drawn over a base image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextSticker {
+    /// Custom stickers showing a name the user entered once for the whole pack.
+    Custom,
+    /// Message stickers showing a text the user enters for every sticker sent.
+    Message,
+}
+
+impl TextSticker {
+    /// Detects text stickers from the `type` of their `data-preview`.
+    pub fn from_type(sticker_type: &str) -> Option<TextSticker> {
+        match sticker_type {
+            "sticker_custom" | "name_text" | "custom" => Some(TextSticker::Custom),
+            "per_sticker_text" | "message" => Some(TextSticker::Message),
+            _ => None,
+        }
+    }
+}
+
+/// Where the user's text is drawn on the base image, in pixels of the base image.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct TextArea {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Returns the text area of a text sticker's `data-preview`, if the store page has one.
+pub fn extract_text_area(preview: &Value) -> Option<TextArea> {
+    serde_json::from_value(preview.get("textArea")?.clone()).ok()
+}
+
+/// Returns the path the sample of a sticker image is written to, e.g. `01_123_sample.png`
+/// for `01_123.png`.
+pub fn sample_path(image_path: &Path) -> PathBuf {
+    let stem = image_path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    image_path.with_file_name(format!("{}_sample.png", stem))
+}
+
+/// Loads the given font or, without one, the first of a few common system fonts.
+pub fn load_font(path: Option<&Path>) -> Result<FontVec, Box<dyn std::error::Error>> {
+    let path = match path {
+        Some(path) => path,
+        None => SYSTEM_FONTS
+            .iter()
+            .map(Path::new)
+            .find(|path| path.is_file())
+            .ok_or("Could not find a font to render the sample text with. Pass one with --font")?,
+    };
+
+    let data = std::fs::read(path)
+        .map_err(|err| format!("Could not read font {}: {}", path.display(), err))?;
+    FontVec::try_from_vec(data).map_err(|_| {
+        format!(
+            "{} is not a valid TrueType or OpenType font",
+            path.display()
+        )
+        .into()
+    })
+}
+
+/// Draws `text` centered into the text area of a base image, shrinking it until it fits.
+/// Without a text area the lower third of the image is used, where LINE places most texts.
+pub fn render_sample(
+    image_path: &Path,
+    output_path: &Path,
+    text: &str,
+    text_area: Option<TextArea>,
+    font: &FontVec,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut image = image::open(image_path)
+        .map_err(|err| format!("Could not open {}: {}", image_path.display(), err))?
+        .to_rgba8();
+
+    let area = text_area.unwrap_or(TextArea {
+        x: 0,
+        y: image.height() * 2 / 3,
+        width: image.width(),
+        height: image.height() / 3,
+    });
+
+    let mut scale = PxScale::from(area.height as f32 * 0.8);
+    let mut text_width = measure_width(font, scale, text);
+    if text_width > area.width as f32 && text_width > 0.0 {
+        scale = PxScale::from(scale.y * area.width as f32 / text_width);
+        text_width = measure_width(font, scale, text);
+    }
+    let text_height = font.as_scaled(scale).height();
+
+    let x = area.x as f32 + (area.width as f32 - text_width) / 2.0;
+    let y = area.y as f32 + (area.height as f32 - text_height) / 2.0;
+    imageproc::drawing::draw_text_mut(
+        &mut image,
+        image::Rgba([0, 0, 0, 255]),
+        x as i32,
+        y as i32,
+        scale,
+        font,
+        text,
+    );
+
+    let mut png = std::io::Cursor::new(Vec::new());
+    image.write_to(&mut png, image::ImageFormat::Png)?;
+    crate::write_file_atomically(output_path, png.get_ref())?;
+
+    Ok(())
+}
+
+fn measure_width(font: &FontVec, scale: PxScale, text: &str) -> f32 {
+    let font = font.as_scaled(scale);
+    let mut width = 0.0;
+    let mut previous = None;
+    for c in text.chars() {
+        let glyph_id = font.glyph_id(c);
+        if let Some(previous) = previous {
+            width += font.kern(previous, glyph_id);
+        }
+        width += font.h_advance(glyph_id);
+        previous = Some(glyph_id);
+    }
+
+    width
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_text_sticker_from_type() {
+        assert_eq!(
+            TextSticker::from_type("sticker_custom"),
+            Some(TextSticker::Custom)
+        );
+        assert_eq!(
+            TextSticker::from_type("name_text"),
+            Some(TextSticker::Custom)
+        );
+        assert_eq!(
+            TextSticker::from_type("per_sticker_text"),
+            Some(TextSticker::Message)
+        );
+        assert_eq!(TextSticker::from_type("animation_sound"), None);
+    }
+
+    #[test]
+    fn test_extract_text_area() {
+        let preview: Value = serde_json::from_str(
+            r#"{ "type": "per_sticker_text", "textArea": { "x": 10, "y": 20, "width": 100, "height": 40 } }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            extract_text_area(&preview),
+            Some(TextArea {
+                x: 10,
+                y: 20,
+                width: 100,
+                height: 40
+            })
+        );
+
+        let preview: Value = serde_json::from_str(r#"{ "type": "per_sticker_text" }"#).unwrap();
+        assert!(extract_text_area(&preview).is_none());
+    }
+
+    #[test]
+    fn test_sample_path() {
+        let actual = sample_path(Path::new("Pack/01_123.png"));
+        assert_eq!(actual, Path::new("Pack").join("01_123_sample.png"));
+    }
+
+    #[test]
+    fn test_load_font_errors() {
+        assert!(load_font(Some(Path::new("missing-font.ttf"))).is_err());
+
+        let path = Path::new("test-not-a-font.ttf");
+        std::fs::write(path, b"not a font").unwrap();
+        let actual = load_font(Some(path));
+        std::fs::remove_file(path).unwrap();
+        assert!(actual.is_err());
+    }
+
+    #[test]
+    fn test_render_sample() {
+        let font =
+            FontVec::try_from_vec(include_bytes!("../tests/fixtures/box.ttf").to_vec()).unwrap();
+
+        let directory = Path::new("Test Render Sample");
+        std::fs::create_dir_all(directory).unwrap();
+        let image_path = directory.join("base.png");
+        let output_path = directory.join("base_sample.png");
+        image::RgbaImage::from_pixel(120, 90, image::Rgba([255, 255, 255, 255]))
+            .save(&image_path)
+            .unwrap();
+
+        let area = TextArea {
+            x: 10,
+            y: 30,
+            width: 100,
+            height: 30,
+        };
+        let actual = render_sample(&image_path, &output_path, "Hello", Some(area), &font);
+        let sample = image::open(&output_path).map(|image| image.to_rgba8());
+        std::fs::remove_dir_all(directory).unwrap();
+        assert!(actual.is_ok(), "{}", actual.unwrap_err());
+
+        let sample = sample.unwrap();
+        assert_eq!(sample.dimensions(), (120, 90));
+        let is_drawn = |x: u32, y: u32| sample.get_pixel(x, y).0 != [255, 255, 255, 255];
+        assert!((10..110).any(|x| (30..60).any(|y| is_drawn(x, y))));
+        assert!(!(0..120).any(|x| (0..25).any(|y| is_drawn(x, y))));
+    }
+}
@@ -0,0 +1,390 @@
+use futures::stream::{self, TryStreamExt};
+use regex::Regex;
+use scraper::{Html, Selector};
+use std::collections::{BTreeMap, HashSet};
+use std::path::PathBuf;
+
+use crate::manifest::{self, FileManifest, PackManifest, StickerManifest};
+use crate::template::PathValues;
+use crate::{
+    download_file, ensure_unique_paths, extract_author_from_document,
+    extract_description_from_document, extract_file_extension, extract_product_id_and_lang,
+    extract_title_from_document, fetch_document, Asset, Config,
+};
+
+/// A file of a theme, either one of its preview images or its resource package.
+#[derive(Debug, PartialEq)]
+struct ThemeFile {
+    asset: Asset,
+    id: String,
+    url: String,
+}
+
+impl ThemeFile {
+    fn file_type(&self) -> &'static str {
+        match self.asset {
+            Asset::Package => "package",
+            _ => "preview",
+        }
+    }
+}
+
+/// Downloads the preview images of a theme and, if the CDN has it, its resource package,
+/// laid out and recorded in the manifest like the stickers of a pack.
+pub async fn download_theme(config: &Config, url: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let document = fetch_document(config, url).await?;
+
+    let title = extract_title_from_document(&document)?;
+    println!("Downloading {}", title);
+
+    let author = extract_author_from_document(&document);
+    let (theme_id, lang) = extract_product_id_and_lang(url);
+    let theme_id = theme_id.ok_or_else(|| format!("Could not find the theme ID in {}", url))?;
+    let lang = lang.unwrap_or(&config.lang);
+
+    let image_urls = extract_theme_image_urls(&document, theme_id);
+    let mut theme_files = Vec::new();
+    if config.assets.contains(&Asset::Image) {
+        for image_url in &image_urls {
+            theme_files.push(ThemeFile {
+                asset: Asset::Image,
+                id: file_stem(image_url).unwrap_or_default().to_string(),
+                url: image_url.clone(),
+            });
+        }
+    }
+    if config.assets.contains(&Asset::Package) {
+        let package_url = image_urls.iter().find_map(|url| theme_package_url(url));
+        if let Some(package_url) = package_url {
+            let available = is_available(config, &package_url)
+                .await
+                .map_err(|err| format!("Could not check {}: {}", package_url, err))?;
+            if available {
+                theme_files.push(ThemeFile {
+                    asset: Asset::Package,
+                    id: "theme".to_string(),
+                    url: package_url,
+                });
+            } else if config.verbose {
+                println!("No resource package found for theme {}", theme_id);
+            }
+        }
+    }
+
+    let mut paths = Vec::new();
+    for (position, file) in theme_files.iter().enumerate() {
+        let ext =
+            extract_file_extension(&file.url).ok_or("Could not extract file extension from url")?;
+        let path = config.template.render(&PathValues {
+            pack_id: theme_id,
+            title: &title,
+            author: author.as_deref().unwrap_or_default(),
+            lang,
+            sticker_id: &file.id,
+            index: position + 1,
+            sticker_type: file.file_type(),
+            suffix: file.asset.suffix(),
+            ext: &ext,
+        });
+        paths.push(config.output.join(path));
+    }
+    ensure_unique_paths(paths.iter())?;
+
+    let downloads = theme_files
+        .iter()
+        .zip(&paths)
+        .map(|(file, path)| Ok((file.url.as_str(), file.id.as_str(), path)));
+    stream::iter(downloads)
+        .try_for_each_concurrent(config.jobs, |(url, id, path)| {
+            download_file(config, url, id, path)
+        })
+        .await?;
+
+    if config.dry_run {
+        return Ok(());
+    }
+
+    if let Some(directory) = manifest::pack_directory(paths.iter().map(PathBuf::as_path)) {
+        let mut files = Vec::new();
+        for (position, (file, path)) in theme_files.iter().zip(&paths).enumerate() {
+            let url_key = match file.asset {
+                Asset::Package => "packageUrl",
+                _ => "imageUrl",
+            };
+            files.push(StickerManifest {
+                id: file.id.clone(),
+                order: position + 1,
+                sticker_type: Some(file.file_type().to_string()),
+                width: None,
+                height: None,
+                text_area: None,
+                urls: BTreeMap::from([(url_key.to_string(), file.url.clone())]),
+                files: vec![FileManifest::from_file(
+                    &directory,
+                    path,
+                    &file.url,
+                    file.asset.name(),
+                )?],
+            });
+        }
+
+        let path = manifest::write_manifest(
+            &directory,
+            &PackManifest {
+                pack_id: Some(theme_id.to_string()),
+                title,
+                author,
+                description: extract_description_from_document(&document),
+                lang: lang.to_string(),
+                source_url: url.to_string(),
+                downloaded_at: humantime::format_rfc3339_seconds(std::time::SystemTime::now())
+                    .to_string(),
+                stickers: files,
+            },
+        )?;
+        if config.verbose {
+            println!("Wrote {}", path.display());
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the urls of the images of a theme shown on its store page, in page order.
+fn extract_theme_image_urls(document: &Html, theme_id: &str) -> Vec<String> {
+    let selector = Selector::parse("img").unwrap();
+    let theme_path = format!("/{}/", theme_id);
+
+    let mut seen_file_names = HashSet::new();
+    document
+        .select(&selector)
+        .filter_map(|element| {
+            let value = element.value();
+            value.attr("src").or_else(|| value.attr("data-src"))
+        })
+        .filter(|src| src.contains("/themeshop/v1/products/") && src.contains(&theme_path))
+        .filter(|src| seen_file_names.insert(file_stem(src).unwrap_or_default().to_string()))
+        .map(String::from)
+        .collect()
+}
+
+/// Derives the url of the Android resource package from the url of any image of the same
+/// theme version, e.g. `.../products/a0/76/83/<id>/1/WEBSTORE/icon_198x278.png` becomes
+/// `.../products/a0/76/83/<id>/1/ANDROID/theme.zip`.
+fn theme_package_url(image_url: &str) -> Option<String> {
+    let version_regex =
+        Regex::new(r"^(https?://[^?#]*/themeshop/v1/products/(?:[^/]+/){4}\d+/)").unwrap();
+    let captures = version_regex.captures(image_url)?;
+
+    Some(format!("{}ANDROID/theme.zip", &captures[1]))
+}
+
+fn file_stem(url: &str) -> Option<&str> {
+    let path = url.split(['?', '#']).next()?;
+    let file_name = path.rsplit('/').next()?;
+    file_name.split('.').next().filter(|stem| !stem.is_empty())
+}
+
+/// Whether the CDN serves a file, checked with a HEAD request. Statuses other than 403 and
+/// 404 count as available and are reported when the file is downloaded.
+async fn is_available(config: &Config, url: &str) -> Result<bool, reqwest::Error> {
+    let response = config.client.head(url).send().await?;
+    Ok(!matches!(response.status().as_u16(), 403 | 404))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    const PNG_BYTES: &[u8] = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+    const THEME_ID: &str = "a0768339-c2d3-4189-9653-2909e9bb6f58";
+
+    fn theme_page(image_base_url: &str) -> String {
+        format!(
+            r#"<p data-test="theme-name-title">Test Theme</p>
+            <a data-test="theme-author">Theme Author</a>
+            <img src="{image_base_url}/themeshop/v1/products/a0/76/83/{THEME_ID}/1/WEBSTORE/icon_198x278.png?__=1">
+            <ul>
+                <li><img src="{image_base_url}/themeshop/v1/products/a0/76/83/{THEME_ID}/1/WEBSTORE/preview_001_198x278.png"></li>
+                <li><img data-src="{image_base_url}/themeshop/v1/products/a0/76/83/{THEME_ID}/1/WEBSTORE/preview_002_198x278.png"></li>
+                <li><img src="{image_base_url}/themeshop/v1/products/a0/76/83/{THEME_ID}/1/WEBSTORE/icon_198x278.png"></li>
+            </ul>
+            <img src="{image_base_url}/themeshop/v1/products/ff/ff/ff/ffffffff-0000-0000-0000-000000000000/1/WEBSTORE/icon_198x278.png">"#
+        )
+    }
+
+    fn delete_directory_if_exists(directory: &Path) {
+        if directory.exists() {
+            std::fs::remove_dir_all(directory).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_extract_theme_image_urls() {
+        let document = Html::parse_document(&theme_page("https://shop.line-scdn.net"));
+        let actual = extract_theme_image_urls(&document, THEME_ID);
+        assert_eq!(
+            actual,
+            vec![
+                format!("https://shop.line-scdn.net/themeshop/v1/products/a0/76/83/{THEME_ID}/1/WEBSTORE/icon_198x278.png?__=1"),
+                format!("https://shop.line-scdn.net/themeshop/v1/products/a0/76/83/{THEME_ID}/1/WEBSTORE/preview_001_198x278.png"),
+                format!("https://shop.line-scdn.net/themeshop/v1/products/a0/76/83/{THEME_ID}/1/WEBSTORE/preview_002_198x278.png"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_theme_package_url() {
+        let actual = theme_package_url(&format!(
+            "https://shop.line-scdn.net/themeshop/v1/products/a0/76/83/{THEME_ID}/12/WEBSTORE/icon_198x278.png?__=1"
+        ));
+        assert_eq!(
+            actual.unwrap(),
+            format!("https://shop.line-scdn.net/themeshop/v1/products/a0/76/83/{THEME_ID}/12/ANDROID/theme.zip")
+        );
+
+        assert!(theme_package_url("https://example.com/icon.png").is_none());
+    }
+
+    #[test]
+    fn test_file_stem() {
+        assert_eq!(
+            file_stem("https://example.com/a/preview_001.png?v=1"),
+            Some("preview_001")
+        );
+        assert_eq!(file_stem("https://example.com/a/"), None);
+    }
+
+    #[tokio::test]
+    async fn test_download_theme() {
+        let mut server = mockito::Server::new_async().await;
+        let url = server.url();
+        let theme_path = format!("/themeshop/v1/products/a0/76/83/{THEME_ID}/1");
+
+        let _m = server
+            .mock("GET", format!("/themeshop/product/{THEME_ID}/en").as_str())
+            .with_status(200)
+            .with_header("content-type", "text/html;charset=UTF-8")
+            .with_body(theme_page(&url))
+            .create_async()
+            .await;
+        let _m2 = server
+            .mock(
+                "GET",
+                mockito::Matcher::Regex(format!(r"^{theme_path}/WEBSTORE/\w+\.png")),
+            )
+            .with_status(200)
+            .with_body(PNG_BYTES)
+            .expect(3)
+            .create_async()
+            .await;
+        let _m3 = server
+            .mock("HEAD", format!("{theme_path}/ANDROID/theme.zip").as_str())
+            .with_status(200)
+            .create_async()
+            .await;
+        let _m4 = server
+            .mock("GET", format!("{theme_path}/ANDROID/theme.zip").as_str())
+            .with_status(200)
+            .with_body(b"PK\x03\x04")
+            .create_async()
+            .await;
+
+        let output = Path::new("Test Theme Output");
+        let config = Config {
+            output: output.to_path_buf(),
+            force: true,
+            ..Config::default()
+        };
+        let actual = download_theme(
+            &config,
+            &format!("{}/themeshop/product/{}/en", url, THEME_ID),
+        )
+        .await;
+        assert!(actual.is_ok(), "{}", actual.unwrap_err());
+
+        let directory = output.join("Test Theme");
+        let exists = [
+            directory.join("01_icon_198x278.png").is_file(),
+            directory.join("02_preview_001_198x278.png").is_file(),
+            directory.join("03_preview_002_198x278.png").is_file(),
+            directory.join("04_theme.zip").is_file(),
+        ];
+        let manifest: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(directory.join("pack.json")).unwrap()).unwrap();
+        delete_directory_if_exists(output);
+
+        assert_eq!(exists, [true; 4]);
+        assert_eq!(manifest["pack_id"], THEME_ID);
+        assert_eq!(manifest["author"], "Theme Author");
+        assert_eq!(manifest["stickers"][3]["type"], "package");
+        assert_eq!(manifest["stickers"][3]["files"][0]["asset"], "package");
+        _m2.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_download_theme_without_package() {
+        let mut server = mockito::Server::new_async().await;
+        let url = server.url();
+
+        let _m = server
+            .mock("GET", format!("/themeshop/product/{THEME_ID}/en").as_str())
+            .with_status(200)
+            .with_header("content-type", "text/html;charset=UTF-8")
+            .with_body(theme_page(&url))
+            .create_async()
+            .await;
+        let _m2 = server
+            .mock(
+                "HEAD",
+                mockito::Matcher::Regex(r"/ANDROID/theme\.zip$".to_string()),
+            )
+            .with_status(404)
+            .create_async()
+            .await;
+
+        let config = Config {
+            dry_run: true,
+            ..Config::default()
+        };
+        let actual = download_theme(
+            &config,
+            &format!("{}/themeshop/product/{}/en", url, THEME_ID),
+        )
+        .await;
+        assert!(actual.is_ok(), "{}", actual.unwrap_err());
+        _m2.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_download_theme_package_unreachable() {
+        let mut server = mockito::Server::new_async().await;
+        let url = server.url();
+
+        // Nothing listens on port 1, so checking the package fails.
+        let _m = server
+            .mock("GET", format!("/themeshop/product/{THEME_ID}/en").as_str())
+            .with_status(200)
+            .with_header("content-type", "text/html;charset=UTF-8")
+            .with_body(theme_page("http://127.0.0.1:1"))
+            .create_async()
+            .await;
+
+        let config = Config {
+            dry_run: true,
+            ..Config::default()
+        };
+        let actual = download_theme(
+            &config,
+            &format!("{}/themeshop/product/{}/en", url, THEME_ID),
+        )
+        .await;
+        let err = actual.unwrap_err().to_string();
+        assert!(
+            err.starts_with("Could not check http://127.0.0.1:1/themeshop/"),
+            "{}",
+            err
+        );
+    }
+}